@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::l_system::*;
+
+// how many generations to derive when looking for a repeated word. only
+// systems with bounded growth can cycle, and those settle quickly in practice
+const MAX_CYCLE_SEARCH: u32 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GrowthClass {
+    Bounded,
+    Polynomial(u32),
+    Exponential(f64),
+}
+
+impl fmt::Display for GrowthClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrowthClass::Bounded => write!(f, "bounded"),
+            GrowthClass::Polynomial(1) => write!(f, "~n"),
+            GrowthClass::Polynomial(k) => write!(f, "~n^{}", k),
+            GrowthClass::Exponential(base) => write!(f, "~{:.3}^n", base),
+        }
+    }
+}
+
+// the word of generation `start` reappears every `period` generations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cycle {
+    pub start: u32,
+    pub period: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GrowthAnalysis {
+    pub class: GrowthClass,
+    pub cycle: Option<Cycle>,
+}
+
+// entries[a][b] is the number of times symbol b occurs in the successor of
+// symbol a. symbols without a rule map to themselves
+#[derive(Clone, Debug, PartialEq)]
+pub struct GrowthMatrix {
+    alphabet: Vec<char>,
+    entries: Vec<Vec<u64>>,
+    start: Vec<u64>,
+}
+
+impl GrowthMatrix {
+    pub fn new(lsystem: &LSystem) -> Self {
+        let rules = lsystem.rules();
+
+        let mut alphabet: Vec<char> = lsystem.start().chars()
+            .chain(rules.keys().copied())
+            .chain(rules.values().flat_map(|s| s.chars()))
+            .collect();
+        alphabet.sort();
+        alphabet.dedup();
+        let index: HashMap<char, usize> =
+            alphabet.iter().enumerate().map(|(i, c)| (*c, i)).collect();
+
+        let mut entries = vec![vec![0; alphabet.len()]; alphabet.len()];
+        for (i, c) in alphabet.iter().enumerate() {
+            match rules.get(c) {
+                Some(successor) => {
+                    for d in successor.chars() {
+                        entries[i][index[&d]] += 1;
+                    }
+                }
+                None => entries[i][i] = 1,
+            }
+        }
+
+        let mut start = vec![0; alphabet.len()];
+        for c in lsystem.start().chars() {
+            start[index[&c]] += 1;
+        }
+
+        Self { alphabet, entries, start }
+    }
+
+    // exact word lengths, generation by generation, computed from symbol
+    // counts rather than by expanding the words. saturates at u64::MAX
+    pub fn lengths(&self) -> impl Iterator<Item=u64> + '_ {
+        let mut counts = Some(self.start.clone());
+        std::iter::from_fn(move || {
+            let current = counts.take()?;
+            let mut next = vec![0u64; current.len()];
+            for (i, count) in current.iter().enumerate() {
+                for (j, n) in self.entries[i].iter().enumerate() {
+                    next[j] = next[j].saturating_add(count.saturating_mul(*n));
+                }
+            }
+            let length = current.iter().fold(0u64, |acc, n| acc.saturating_add(*n));
+            counts = Some(next);
+            Some(length)
+        })
+    }
+
+    pub fn class(&self) -> GrowthClass {
+        let reachable = self.reachable();
+        let components = self.components(&reachable);
+
+        let mut component_of = vec![usize::MAX; self.alphabet.len()];
+        for (k, component) in components.iter().enumerate() {
+            for i in component.iter() {
+                component_of[*i] = k;
+            }
+        }
+
+        let radii: Vec<f64> = components.iter().map(|c| self.spectral_radius(c)).collect();
+        let radius = radii.iter().cloned().fold(0.0, f64::max);
+        if radius > 1.0 + 1e-9 {
+            return GrowthClass::Exponential(radius);
+        }
+        if radius == 0.0 {
+            return GrowthClass::Bounded;
+        }
+
+        // every non trivial component is a simple cycle. the degree of the
+        // polynomial is one less than the largest number of cycles along a
+        // path through the component graph. components come out of tarjan's
+        // algorithm in reverse topological order, so successors are done first
+        let mut chain = vec![0u32; components.len()];
+        for (k, component) in components.iter().enumerate() {
+            let mut best = 0;
+            for i in component.iter() {
+                for (j, n) in self.entries[*i].iter().enumerate() {
+                    if *n > 0 && component_of[j] != k {
+                        best = best.max(chain[component_of[j]]);
+                    }
+                }
+            }
+            chain[k] = best + if radii[k] > 0.0 { 1 } else { 0 };
+        }
+        match chain.iter().max().copied().unwrap_or(0) {
+            0 | 1 => GrowthClass::Bounded,
+            d => GrowthClass::Polynomial(d - 1),
+        }
+    }
+
+    fn reachable(&self) -> Vec<bool> {
+        let mut reachable: Vec<bool> = self.start.iter().map(|n| *n > 0).collect();
+        let mut pending: Vec<usize> = (0..self.alphabet.len()).filter(|i| reachable[*i]).collect();
+        while let Some(i) = pending.pop() {
+            for (j, n) in self.entries[i].iter().enumerate() {
+                if *n > 0 && !reachable[j] {
+                    reachable[j] = true;
+                    pending.push(j);
+                }
+            }
+        }
+        reachable
+    }
+
+    // strongly connected components of the reachable symbols (tarjan)
+    fn components(&self, reachable: &[bool]) -> Vec<Vec<usize>> {
+        struct Search<'a> {
+            entries: &'a [Vec<u64>],
+            index: Vec<Option<usize>>,
+            low: Vec<usize>,
+            on_stack: Vec<bool>,
+            stack: Vec<usize>,
+            counter: usize,
+            components: Vec<Vec<usize>>,
+        }
+
+        fn visit(search: &mut Search, i: usize) {
+            search.index[i] = Some(search.counter);
+            search.low[i] = search.counter;
+            search.counter += 1;
+            search.stack.push(i);
+            search.on_stack[i] = true;
+
+            for j in 0..search.entries.len() {
+                if search.entries[i][j] == 0 {
+                    continue;
+                }
+                match search.index[j] {
+                    None => {
+                        visit(search, j);
+                        search.low[i] = search.low[i].min(search.low[j]);
+                    }
+                    Some(index) if search.on_stack[j] => {
+                        search.low[i] = search.low[i].min(index);
+                    }
+                    _ => {}
+                }
+            }
+
+            if Some(search.low[i]) == search.index[i] {
+                let mut component = Vec::new();
+                while let Some(j) = search.stack.pop() {
+                    search.on_stack[j] = false;
+                    component.push(j);
+                    if j == i {
+                        break;
+                    }
+                }
+                search.components.push(component);
+            }
+        }
+
+        let n = self.alphabet.len();
+        let mut search = Search {
+            entries: &self.entries,
+            index: vec![None; n],
+            low: vec![0; n],
+            on_stack: vec![false; n],
+            stack: Vec::new(),
+            counter: 0,
+            components: Vec::new(),
+        };
+        for (i, reachable) in reachable.iter().enumerate() {
+            if *reachable && search.index[i].is_none() {
+                visit(&mut search, i);
+            }
+        }
+        search.components
+    }
+
+    // largest eigenvalue of the matrix restricted to a strongly connected
+    // component. a non negative integer matrix that is irreducible has
+    // spectral radius 1 exactly when it is a permutation cycle, otherwise the
+    // radius is found by power iteration on I + A, which is primitive
+    fn spectral_radius(&self, component: &[usize]) -> f64 {
+        let inner = |i: usize| -> u64 {
+            component.iter().map(|j| self.entries[i][*j]).sum()
+        };
+
+        if component.len() == 1 && inner(component[0]) == 0 {
+            return 0.0;
+        }
+        if component.iter().all(|i| inner(*i) == 1) {
+            return 1.0;
+        }
+
+        let mut v = vec![1.0; component.len()];
+        let mut radius = 0.0;
+        for _ in 0..10000 {
+            let mut next = v.clone();
+            for (a, i) in component.iter().enumerate() {
+                for (b, j) in component.iter().enumerate() {
+                    next[b] += v[a] * self.entries[*i][*j] as f64;
+                }
+            }
+            let norm = next.iter().cloned().fold(0.0, f64::max);
+            for x in next.iter_mut() {
+                *x /= norm;
+            }
+            let converged = (norm - radius).abs() < 1e-12;
+            radius = norm;
+            v = next;
+            if converged {
+                break;
+            }
+        }
+        radius - 1.0
+    }
+}
+
+pub fn analyze(lsystem: &LSystem) -> GrowthAnalysis {
+    let class = GrowthMatrix::new(lsystem).class();
+    let cycle = if class == GrowthClass::Bounded {
+        find_cycle(lsystem, MAX_CYCLE_SEARCH)
+    } else {
+        None
+    };
+    GrowthAnalysis { class, cycle }
+}
+
+pub fn find_cycle(lsystem: &LSystem, max_generations: u32) -> Option<Cycle> {
+    let mut seen: HashMap<String, u32> = HashMap::new();
+    for generation in lsystem.generations().take(max_generations as usize + 1) {
+        if let Some(start) = seen.get(&generation.word) {
+            return Some(Cycle { start: *start, period: generation.index - start });
+        }
+        seen.insert(generation.word, generation.index);
+    }
+    None
+}
+
+// the largest number of iterations, up to `limit`, whose word is no longer
+// than `max_length`
+pub fn max_iterations(lsystem: &LSystem, max_length: u64, limit: u32) -> u32 {
+    let matrix = GrowthMatrix::new(lsystem);
+    let mut result = 0;
+    for (n, length) in matrix.lengths().take(limit as usize + 1).enumerate() {
+        if length > max_length {
+            break;
+        }
+        result = n as u32;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::*;
+
+    fn system(start: &str, rules: &[(char, &str)]) -> LSystem {
+        LSystem::new(
+            start,
+            rules.iter().map(|(k, v)| (*k, v.to_string())).collect(),
+            HashMap::new(),
+        )
+    }
+
+    fn assert_exponential(class: GrowthClass, expected: f64) {
+        match class {
+            GrowthClass::Exponential(base) => assert!((base - expected).abs() < 1e-6, "{}", base),
+            _ => panic!("expected exponential growth, got {:?}", class),
+        }
+    }
+
+    #[test]
+    fn exponential() {
+        assert_exponential(analyze(&ALGAE.2()).class, (1.0 + 5f64.sqrt()) / 2.0);
+        assert_exponential(analyze(&KOCH.2()).class, 5.0);
+        assert_exponential(analyze(&SIERPINSKI.2()).class, 3.0);
+        assert_exponential(analyze(&TREE.2()).class, 2.0);
+    }
+
+    #[test]
+    fn polynomial() {
+        assert_eq!(analyze(&system("A", &[('A', "AB")])).class, GrowthClass::Polynomial(1));
+        assert_eq!(analyze(&system("A", &[('A', "AB"), ('B', "BC")])).class, GrowthClass::Polynomial(2));
+        assert_eq!(analyze(&system("A", &[('A', "BC"), ('B', "A")])).class, GrowthClass::Polynomial(1));
+    }
+
+    #[test]
+    fn bounded() {
+        let analysis = analyze(&system("A", &[('A', "B"), ('B', "A")]));
+        assert_eq!(analysis.class, GrowthClass::Bounded);
+        assert_eq!(analysis.cycle, Some(Cycle { start: 0, period: 2 }));
+
+        let analysis = analyze(&system("AB", &[('A', "C"), ('B', "")]));
+        assert_eq!(analysis.class, GrowthClass::Bounded);
+        assert_eq!(analysis.cycle, Some(Cycle { start: 1, period: 1 }));
+
+        let analysis = analyze(&system("A", &[('A', "B"), ('B', "")]));
+        assert_eq!(analysis.class, GrowthClass::Bounded);
+        assert_eq!(analysis.cycle, Some(Cycle { start: 2, period: 1 }));
+    }
+
+    #[test]
+    fn unreachable_symbols_are_ignored() {
+        let analysis = analyze(&system("A", &[('A', "A"), ('B', "BB")]));
+        assert_eq!(analysis.class, GrowthClass::Bounded);
+        assert_eq!(analysis.cycle, Some(Cycle { start: 0, period: 1 }));
+    }
+
+    #[test]
+    fn lengths_match_generations() {
+        let lsystem = PLANT.2();
+        let matrix = GrowthMatrix::new(&lsystem);
+        for (length, generation) in matrix.lengths().zip(lsystem.generations()).take(6) {
+            assert_eq!(length, generation.length as u64);
+        }
+        assert_eq!(max_iterations(&ALGAE.2(), 8, 100), 4);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(GrowthClass::Exponential((1.0 + 5f64.sqrt()) / 2.0).to_string(), "~1.618^n");
+        assert_eq!(GrowthClass::Polynomial(2).to_string(), "~n^2");
+        assert_eq!(GrowthClass::Bounded.to_string(), "bounded");
+    }
+}
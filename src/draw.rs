@@ -1,83 +1,64 @@
-use web_sys;
 use wasm_bindgen::prelude::*;
-use futures::stream::{Stream, StreamExt};
-use std::pin::{Pin};
+
+// the color behind every drawing
+pub const BACKGROUND: &str = "#111111";
 
 #[wasm_bindgen]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Viewport {
     pub x0: f64, pub x1: f64, pub y0: f64, pub y1: f64
 }
 
-#[derive(Clone, Debug)]
+impl Viewport {
+    // the region a `width` by `height` canvas shows when `bounds` is
+    // centered on it, as large as it fits with `margin` pixels to spare on
+    // every side
+    pub fn fit(bounds: Viewport, width: f64, height: f64, margin: f64) -> Viewport {
+        let (w, h) = (bounds.x1 - bounds.x0, bounds.y1 - bounds.y0);
+        let room = |size: f64| (size - 2.0 * margin).max(1.0);
+        // drawing units per pixel. a single point is shown at 1
+        let scale = match (w / room(width)).max(h / room(height)) {
+            scale if scale > 0.0 => scale,
+            _ => 1.0,
+        };
+        let (cx, cy) = ((bounds.x0 + bounds.x1) / 2.0, (bounds.y0 + bounds.y1) / 2.0);
+        let (half_width, half_height) = (width * scale / 2.0, height * scale / 2.0);
+        Viewport { x0: cx - half_width, x1: cx + half_width, y0: cy - half_height, y1: cy + half_height }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum DrawCommand {
     BeginPath,
     SetLineWidth(f64),
     SetStrokeStyle(String),
+    SetFillStyle(String),
     MoveTo(f64, f64),
     LineTo(f64, f64),
+    // center, radius and start and end angles in radians. the arc runs
+    // counter clockwise (with y pointing up) when end is greater than start
+    Arc(f64, f64, f64, f64, f64),
+    ClosePath,
     Stroke,
+    Fill,
 }
 
-impl DrawCommand {
-    fn exec(&self,
-            context: &web_sys::CanvasRenderingContext2d,
-            delta_x: f64,
-            delta_y: f64,
-            alpha: f64,
-            beta: f64) {
-        match self {
-            DrawCommand::BeginPath => {
-                context.begin_path();
-            }
-            DrawCommand::SetLineWidth(lw) => {
-                context.set_line_width(*lw);
-            }
-            DrawCommand::SetStrokeStyle(s) => {
-                context.set_stroke_style(&JsValue::from_str(&s));
-            }
-            DrawCommand::MoveTo(x, y) => {
-                let x = (*x - delta_x) * alpha;
-                let y = (*y - delta_y) * beta;
-                context.move_to(x, y);
-            }
-            DrawCommand::LineTo(x, y) => {
-                let x = (*x - delta_x) * alpha;
-                let y = (*y - delta_y) * beta;
-                context.line_to(x, y);
-            }
-            DrawCommand::Stroke => {
-                context.stroke();
-            }
-        }
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub fn exec_all(commands: &Vec<DrawCommand>,
-                    ctx: &web_sys::CanvasRenderingContext2d,
-                    viewport: Viewport) {
-        let canvas = ctx.canvas().expect("canvas missing!");
-        let (width, height) = (canvas.width() as f64, canvas.height() as f64);
-        let Viewport { x0, x1, y0, y1 } = viewport;
-        let alpha = width / (x1 - x0);
-        let beta = height / (y0 - y1);
+    #[test]
+    fn fit() {
+        let bounds = Viewport { x0: 0.0, x1: 100.0, y0: 10.0, y1: 60.0 };
+        // limited by the width: 100 units across 200 - 2 * 50 pixels
+        assert_eq!(Viewport::fit(bounds, 200.0, 400.0, 50.0), Viewport { x0: -50.0, x1: 150.0, y0: -165.0, y1: 235.0 });
+        // limited by the height: 50 units across 100 pixels
+        assert_eq!(Viewport::fit(bounds, 400.0, 100.0, 0.0), Viewport { x0: -50.0, x1: 150.0, y0: 10.0, y1: 60.0 });
 
-        for command in commands.iter() {
-            command.exec(ctx, x0, y1, alpha, beta);
-        }
-    }
-
-    pub async fn exec_stream(commands: &mut Pin<Box<dyn Stream<Item=DrawCommand>>>,
-                             ctx: &web_sys::CanvasRenderingContext2d,
-                             viewport: Viewport) {
-        let canvas = ctx.canvas().expect("canvas missing!");
-        let (width, height) = (canvas.width() as f64, canvas.height() as f64);
-        let Viewport { x0, x1, y0, y1 } = viewport;
-        let alpha = width / (x1 - x0);
-        let beta = height / (y0 - y1);
-
-        while let Some(command) = commands.next().await {
-            command.exec(ctx, x0, y1, alpha, beta);
-        }
+        let point = Viewport { x0: 5.0, x1: 5.0, y0: 5.0, y1: 5.0 };
+        assert_eq!(Viewport::fit(point, 20.0, 10.0, 2.0), Viewport { x0: -5.0, x1: 15.0, y0: 0.0, y1: 10.0 });
     }
 }
-
@@ -0,0 +1,482 @@
+use std::fmt::Write;
+
+use crate::render::*;
+use crate::turtle::*;
+use crate::vector::*;
+
+// the drawing units recorded in the header, for the tools importing the file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Units {
+    Unitless,
+    Inches,
+    Millimeters,
+    Centimeters,
+    Meters,
+}
+
+impl Units {
+    // $INSUNITS
+    fn code(&self) -> i32 {
+        match self {
+            Units::Unitless => 0,
+            Units::Inches => 1,
+            Units::Millimeters => 4,
+            Units::Centimeters => 5,
+            Units::Meters => 6,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DxfOptions {
+    pub units: Units,
+    // drawing coordinates are multiplied by this
+    pub scale: f64,
+    // digits after the decimal point in coordinates
+    pub precision: usize,
+}
+
+impl Default for DxfOptions {
+    fn default() -> Self {
+        Self { units: Units::Millimeters, scale: 1.0, precision: 3 }
+    }
+}
+
+// the points of a LINE or LWPOLYLINE, whether it's closed and its layer
+type Entity = (Vec<Point>, bool, String);
+
+// layer names and the true color each layer is drawn in, if any
+type Layers = Vec<(String, Option<[u8; 3]>)>;
+
+// a layer for every color in the drawing, named after its red, green and
+// blue. styles that can't be read get a numbered layer each
+struct ColorLayers {
+    // each style seen and the name of its layer
+    styles: Vec<(String, String)>,
+    layers: Layers,
+    unreadable: usize,
+}
+
+impl ColorLayers {
+    fn layer(&mut self, style: &str) -> String {
+        if let Some((_, name)) = self.styles.iter().find(|(s, _)| s == style) {
+            return name.clone();
+        }
+        let color = parse_color(style);
+        let name = match color {
+            Some([r, g, b]) => format!("COLOR_{:02X}{:02X}{:02X}", r, g, b),
+            None => {
+                self.unreadable += 1;
+                format!("STYLE_{}", self.unreadable)
+            }
+        };
+        if !self.layers.iter().any(|(layer, _)| *layer == name) {
+            self.layers.push((name.clone(), color));
+        }
+        self.styles.push((style.to_string(), name.clone()));
+        name
+    }
+}
+
+// writes group codes and values, one per line
+struct Groups {
+    text: String,
+    // the last handle given out
+    handle: u32,
+}
+
+impl Groups {
+    fn add(&mut self, code: i32, value: impl std::fmt::Display) {
+        let _ = writeln!(self.text, "{:>3}\n{}", code, value);
+    }
+
+    // a new handle, for an object that hasn't been written yet
+    fn handle(&mut self) -> String {
+        self.handle += 1;
+        format!("{:X}", self.handle)
+    }
+
+    // starts an object with a handle of its own, owned by `owner`
+    fn object(&mut self, kind: &str, owner: &str) -> String {
+        let handle = self.handle();
+        self.add(0, kind);
+        self.add(5, &handle);
+        self.add(330, owner);
+        handle
+    }
+
+    // starts a table of `entries` records
+    fn table(&mut self, name: &str, entries: usize) -> String {
+        self.add(0, "TABLE");
+        self.add(2, name);
+        let handle = self.handle();
+        self.add(5, &handle);
+        self.add(330, 0);
+        self.add(100, "AcDbSymbolTable");
+        self.add(70, entries);
+        handle
+    }
+
+    // starts a record of a table
+    fn record(&mut self, kind: &str, table: &str, subclass: &str, name: &str) -> String {
+        let handle = self.object(kind, table);
+        self.add(100, "AcDbSymbolTableRecord");
+        self.add(100, subclass);
+        self.add(2, name);
+        self.add(70, 0);
+        handle
+    }
+
+    // the start and end of the block of a block record
+    fn block(&mut self, record: &str, name: &str, paper: bool) {
+        for (kind, subclass) in [("BLOCK", "AcDbBlockBegin"), ("ENDBLK", "AcDbBlockEnd")] {
+            self.object(kind, record);
+            self.add(100, "AcDbEntity");
+            if paper {
+                self.add(67, 1);
+            }
+            self.add(8, 0);
+            self.add(100, subclass);
+            if kind == "BLOCK" {
+                self.add(2, name);
+                self.add(70, 0);
+                self.add(10, 0);
+                self.add(20, 0);
+                self.add(30, 0);
+                self.add(3, name);
+                self.add(1, "");
+            }
+        }
+    }
+}
+
+// every stroke as a LINE, or an LWPOLYLINE when it has more than one
+// segment, and the outline of every fill as closed LWPOLYLINEs. each color
+// goes on a layer of its own, drawn in that color. y points up, like the
+// drawing
+pub fn dxf(geometry: &Geometry, options: &DxfOptions) -> String {
+    let mut layers = ColorLayers { styles: Vec::new(), layers: Vec::new(), unreadable: 0 };
+    let mut entities: Vec<Entity> = Vec::new();
+    for shape in geometry.shapes.iter() {
+        match shape {
+            Shape::Polyline(polyline) => {
+                let layer = layers.layer(&polyline.color);
+                entities.push((polyline.points.clone(), polyline.closed && polyline.points.len() > 2, layer));
+            }
+            Shape::Fill(fill) => {
+                let layer = layers.layer(&fill.color);
+                entities.extend(fill.rings.iter().map(|ring| (ring.clone(), true, layer.clone())));
+            }
+        }
+    }
+    write(entities, &layers.layers, options)
+}
+
+// what a program drew, projected the way the drawing is, with each branch
+// depth on a layer of its own. lines that carry on from the one before at
+// the same depth are joined into one LWPOLYLINE, and the outline of every
+// polygon is closed
+pub fn dxf_depths(model: &Model, projection: &Projection, options: &DxfOptions) -> String {
+    let mut entities: Vec<(Vec<Point>, bool, usize)> = Vec::new();
+    let mut last: Option<(Vec3, usize)> = None;
+    for segment in model.segments.iter() {
+        let (start, end) = (projection.project(segment.start), projection.project(segment.end));
+        match entities.last_mut() {
+            Some((points, _, _)) if last == Some((segment.start, segment.depth)) => points.push(end),
+            _ => entities.push((vec![start, end], false, segment.depth)),
+        }
+        last = Some((segment.end, segment.depth));
+    }
+    for polygon in model.polygons.iter().filter(|polygon| polygon.points.len() > 2) {
+        let points = polygon.points.iter().map(|p| projection.project(*p)).collect();
+        entities.push((points, true, polygon.depth));
+    }
+
+    let mut depths: Vec<usize> = entities.iter().map(|(_, _, depth)| *depth).collect();
+    depths.sort_unstable();
+    depths.dedup();
+    let layer = |depth: usize| format!("DEPTH_{}", depth);
+    let layers: Layers = depths.into_iter().map(|depth| (layer(depth), None)).collect();
+    let entities = entities.into_iter().map(|(points, closed, depth)| (points, closed, layer(depth))).collect();
+    write(entities, &layers, options)
+}
+
+fn write(mut entities: Vec<Entity>, layers: &Layers, options: &DxfOptions) -> String {
+    let n = |v: f64| number(v * options.scale, options.precision);
+    entities.retain(|(points, _, _)| points.len() > 1);
+
+    // autocad 2000 and later want every table, the blocks of model and
+    // paper space and the root dictionary, and every object owned by another
+    let mut groups = Groups { text: String::new(), handle: 0 };
+    groups.add(0, "SECTION");
+    groups.add(2, "TABLES");
+    groups.table("VPORT", 0);
+    groups.add(0, "ENDTAB");
+
+    let table = groups.table("LTYPE", 3);
+    for (name, description) in [("ByBlock", ""), ("ByLayer", ""), ("CONTINUOUS", "Solid line")] {
+        groups.record("LTYPE", &table, "AcDbLinetypeTableRecord", name);
+        groups.add(3, description);
+        groups.add(72, 65);
+        groups.add(73, 0);
+        groups.add(40, 0);
+    }
+    groups.add(0, "ENDTAB");
+
+    let table = groups.table("LAYER", layers.len() + 1);
+    for (name, color) in [("0".to_string(), None)].iter().chain(layers.iter()) {
+        groups.record("LAYER", &table, "AcDbLayerTableRecord", name);
+        // white on a dark screen and black on paper, unless the true color
+        // says otherwise
+        groups.add(62, 7);
+        if let Some([r, g, b]) = color {
+            groups.add(420, (*r as u32) << 16 | (*g as u32) << 8 | *b as u32);
+        }
+        groups.add(6, "CONTINUOUS");
+    }
+    groups.add(0, "ENDTAB");
+
+    let table = groups.table("STYLE", 1);
+    groups.record("STYLE", &table, "AcDbTextStyleTableRecord", "Standard");
+    groups.add(40, 0);
+    groups.add(41, 1);
+    groups.add(50, 0);
+    groups.add(71, 0);
+    groups.add(42, 2.5);
+    groups.add(3, "txt");
+    groups.add(4, "");
+    groups.add(0, "ENDTAB");
+
+    for name in ["VIEW", "UCS"] {
+        groups.table(name, 0);
+        groups.add(0, "ENDTAB");
+    }
+
+    let table = groups.table("APPID", 1);
+    groups.record("APPID", &table, "AcDbRegAppTableRecord", "ACAD");
+    groups.add(0, "ENDTAB");
+
+    groups.table("DIMSTYLE", 0);
+    groups.add(100, "AcDbDimStyleTable");
+    groups.add(71, 0);
+    groups.add(0, "ENDTAB");
+
+    let table = groups.table("BLOCK_RECORD", 2);
+    let model = groups.record("BLOCK_RECORD", &table, "AcDbBlockTableRecord", "*Model_Space");
+    let paper = groups.record("BLOCK_RECORD", &table, "AcDbBlockTableRecord", "*Paper_Space");
+    groups.add(0, "ENDTAB");
+    groups.add(0, "ENDSEC");
+
+    groups.add(0, "SECTION");
+    groups.add(2, "BLOCKS");
+    groups.block(&model, "*Model_Space", false);
+    groups.block(&paper, "*Paper_Space", true);
+    groups.add(0, "ENDSEC");
+
+    groups.add(0, "SECTION");
+    groups.add(2, "ENTITIES");
+    for (points, closed, layer) in entities.iter() {
+        if points.len() == 2 && !closed {
+            let [(x0, y0), (x1, y1)] = [points[0], points[1]];
+            groups.object("LINE", &model);
+            groups.add(100, "AcDbEntity");
+            groups.add(8, layer);
+            groups.add(100, "AcDbLine");
+            groups.add(10, n(x0));
+            groups.add(20, n(y0));
+            groups.add(30, 0);
+            groups.add(11, n(x1));
+            groups.add(21, n(y1));
+            groups.add(31, 0);
+        } else {
+            groups.object("LWPOLYLINE", &model);
+            groups.add(100, "AcDbEntity");
+            groups.add(8, layer);
+            groups.add(100, "AcDbPolyline");
+            groups.add(90, points.len());
+            groups.add(70, if *closed { 1 } else { 0 });
+            for (x, y) in points.iter() {
+                groups.add(10, n(*x));
+                groups.add(20, n(*y));
+            }
+        }
+    }
+    groups.add(0, "ENDSEC");
+
+    groups.add(0, "SECTION");
+    groups.add(2, "OBJECTS");
+    let root = groups.object("DICTIONARY", "0");
+    groups.add(100, "AcDbDictionary");
+    groups.add(281, 1);
+    groups.add(3, "ACAD_GROUP");
+    let group = groups.handle();
+    groups.add(350, &group);
+    groups.add(0, "DICTIONARY");
+    groups.add(5, &group);
+    groups.add(330, &root);
+    groups.add(100, "AcDbDictionary");
+    groups.add(281, 1);
+    groups.add(0, "ENDSEC");
+    groups.add(0, "EOF");
+
+    // the header comes first, but the next free handle is only known now
+    let mut header = Groups { text: String::new(), handle: 0 };
+    header.add(0, "SECTION");
+    header.add(2, "HEADER");
+    header.add(9, "$ACADVER");
+    // true colors need at least autocad 2004
+    header.add(1, "AC1018");
+    header.add(9, "$HANDSEED");
+    header.add(5, format!("{:X}", groups.handle + 1));
+    header.add(9, "$INSUNITS");
+    header.add(70, options.units.code());
+    header.add(9, "$MEASUREMENT");
+    header.add(70, if options.units == Units::Inches { 0 } else { 1 });
+    header.add(0, "ENDSEC");
+    header.text + &groups.text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw::*;
+
+    fn groups(actual: &str) -> Vec<(&str, &str)> {
+        let lines: Vec<&str> = actual.lines().map(|line| line.trim()).collect();
+        lines.chunks(2).map(|pair| (pair[0], pair[1])).collect()
+    }
+
+    #[test]
+    fn entities() {
+        let mut geometry = Geometry::new();
+        geometry.render_all(&[
+            DrawCommand::SetStrokeStyle("#ff0000".to_string()),
+            DrawCommand::MoveTo(0.0, 0.0),
+            DrawCommand::LineTo(1.0, 0.0),
+            DrawCommand::MoveTo(0.0, 1.0),
+            DrawCommand::LineTo(1.0, 1.0),
+            DrawCommand::LineTo(1.0, 2.0),
+            DrawCommand::ClosePath,
+            DrawCommand::Stroke,
+        ]);
+        let actual = dxf(&geometry, &DxfOptions { units: Units::Inches, scale: 2.0, ..DxfOptions::default() });
+        let groups = groups(&actual);
+        let after = |code: &str, value: &str| {
+            let i = groups.iter().position(|group| *group == (code, value)).unwrap();
+            groups[i + 1..].iter().take_while(|(code, _)| *code != "0" && *code != "9").cloned().collect::<Vec<_>>()
+        };
+
+        assert_eq!(after("9", "$ACADVER"), vec![("1", "AC1018")]);
+        assert_eq!(after("9", "$INSUNITS"), vec![("70", "1")]);
+        assert_eq!(after("9", "$MEASUREMENT"), vec![("70", "0")]);
+        assert!(after("2", "COLOR_FF0000").contains(&("420", "16711680")));
+        assert_eq!(after("0", "LINE")[3..], [
+            ("8", "COLOR_FF0000"), ("100", "AcDbLine"),
+            ("10", "0"), ("20", "0"), ("30", "0"), ("11", "2"), ("21", "0"), ("31", "0"),
+        ]);
+        assert_eq!(after("0", "LWPOLYLINE")[3..], [
+            ("8", "COLOR_FF0000"), ("100", "AcDbPolyline"), ("90", "3"), ("70", "1"),
+            ("10", "0"), ("20", "2"), ("10", "2"), ("20", "2"), ("10", "2"), ("20", "4"),
+        ]);
+        assert_eq!(groups.last(), Some(&("0", "EOF")));
+    }
+
+    #[test]
+    fn structure() {
+        let actual = dxf(&crate::render::tests::drawing(), &DxfOptions::default());
+        let groups = groups(&actual);
+        let named = |kind: &str| {
+            let names = groups.windows(2).filter(|pair| pair[0] == ("0", kind) && pair[1].0 == "2");
+            names.map(|pair| pair[1].1).collect::<Vec<_>>()
+        };
+        assert_eq!(named("SECTION"), ["HEADER", "TABLES", "BLOCKS", "ENTITIES", "OBJECTS"]);
+        assert_eq!(named("TABLE"), ["VPORT", "LTYPE", "LAYER", "STYLE", "VIEW", "UCS", "APPID", "DIMSTYLE", "BLOCK_RECORD"]);
+        assert_eq!(groups.iter().filter(|group| **group == ("0", "ENDTAB")).count(), 9);
+        assert!(actual.contains("  2\nCONTINUOUS\n 70\n0\n  3\nSolid line\n"));
+        assert!(actual.contains("  2\n0\n 70\n0\n 62\n7\n  6\nCONTINUOUS\n"));
+        assert!(actual.contains("  3\nACAD_GROUP\n350\n"));
+
+        // every handle is different and below the seed, and every owner is
+        // an object in the file
+        let handles: Vec<u32> = groups.iter().filter(|(code, _)| *code == "5").map(|(_, h)| u32::from_str_radix(h, 16).unwrap()).collect();
+        // the first is $HANDSEED
+        let (seed, handles) = (handles[0], &handles[1..]);
+        assert!(handles.iter().all(|h| *h < seed));
+        assert_eq!(handles.iter().collect::<std::collections::HashSet<_>>().len(), handles.len());
+        for (_, owner) in groups.iter().filter(|(code, _)| *code == "330") {
+            let owner = u32::from_str_radix(owner, 16).unwrap();
+            assert!(owner == 0 || handles.contains(&owner));
+        }
+
+        // the blocks and the entities belong to the records of model and
+        // paper space
+        let record = |name: &str| {
+            let i = groups.iter().position(|group| *group == ("2", name)).unwrap();
+            groups[..i].iter().rev().find(|(code, _)| *code == "5").unwrap().1
+        };
+        let (model, paper) = (record("*Model_Space"), record("*Paper_Space"));
+        let blocks = groups.windows(2).filter(|pair| pair[0] == ("100", "AcDbBlockBegin"));
+        assert_eq!(blocks.map(|pair| pair[1]).collect::<Vec<_>>(), [("2", "*Model_Space"), ("2", "*Paper_Space")]);
+        let owners = |kind: &str| {
+            let starts = groups.iter().enumerate().filter(|(_, group)| **group == ("0", kind));
+            starts.map(|(i, _)| groups[i + 2].1).collect::<Vec<_>>()
+        };
+        assert_eq!(owners("BLOCK"), [model, paper]);
+        assert_eq!(owners("ENDBLK"), [model, paper]);
+        assert_eq!(owners("LWPOLYLINE"), [model]);
+        assert_eq!(owners("LINE"), [model, model]);
+    }
+
+    // the layer names in the table, but for layer 0, and the layer of
+    // every entity
+    fn layers(actual: &str) -> (Vec<String>, Vec<String>) {
+        let groups = groups(actual);
+        let start = groups.iter().position(|group| *group == ("2", "LAYER")).unwrap();
+        let end = start + groups[start..].iter().position(|group| *group == ("0", "ENDTAB")).unwrap();
+        let entities = groups.iter().position(|group| *group == ("2", "ENTITIES")).unwrap();
+        let table = groups[start + 1..end].iter().filter(|(code, _)| *code == "2").skip(1).map(|(_, name)| name.to_string());
+        let on = groups[entities..].iter().filter(|(code, _)| *code == "8").map(|(_, name)| name.to_string());
+        (table.collect(), on.collect())
+    }
+
+    #[test]
+    fn color_layers() {
+        let mut geometry = Geometry::new();
+        for style in ["#ff0000", "#f00", "rgb(255,0,0)", "red", "rgb(255,0,0)", "#00FF80"] {
+            geometry.render_all(&[
+                DrawCommand::SetStrokeStyle(style.to_string()),
+                DrawCommand::MoveTo(0.0, 0.0),
+                DrawCommand::LineTo(1.0, 0.0),
+                DrawCommand::Stroke,
+                DrawCommand::BeginPath,
+            ]);
+        }
+        let (table, on) = layers(&dxf(&geometry, &DxfOptions::default()));
+        assert_eq!(table, ["COLOR_FF0000", "STYLE_1", "STYLE_2", "COLOR_00FF80"]);
+        assert_eq!(on, ["COLOR_FF0000", "COLOR_FF0000", "STYLE_1", "STYLE_2", "STYLE_1", "COLOR_00FF80"]);
+    }
+
+    #[test]
+    fn depth_layers() {
+        let pen = Pen { color: (1.0, 1.0, 1.0), width: 1.0, state: PenState::Down };
+        let model = TurtleProgram::new(Turtle::new(pen), vec![
+            TurtleCommand::Move(1.0),
+            TurtleCommand::Push,
+            TurtleCommand::Turn(90.0),
+            TurtleCommand::Move(1.0),
+            TurtleCommand::Move(1.0),
+            TurtleCommand::Pop,
+            TurtleCommand::Move(1.0),
+            TurtleCommand::Push,
+            TurtleCommand::Dot(1.0),
+            TurtleCommand::Pop,
+        ]).model().unwrap();
+        let actual = dxf_depths(&model, &Projection::default(), &DxfOptions::default());
+        let (table, on) = layers(&actual);
+        assert_eq!(table, ["DEPTH_0", "DEPTH_1"]);
+        assert_eq!(on, ["DEPTH_0", "DEPTH_1", "DEPTH_0", "DEPTH_1"]);
+        // the branch is one polyline
+        assert!(actual.contains("DEPTH_1\n100\nAcDbPolyline\n 90\n3\n 70\n0\n"));
+        assert!(!actual.lines().any(|line| line == "420"));
+    }
+}
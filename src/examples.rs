@@ -7,15 +7,11 @@ use crate::turtle::*;
 
 pub fn turtle_example() -> TurtleProgram {
     TurtleProgram::new(
-        Turtle {
-            location: (0.0, 0.0),
-            orientation: 0.0,
-            pen: Pen {
-                color: (1.0, 1.0, 1.0),
-                width: 3.0,
-                state: PenState::Down,
-            }
-        },
+        Turtle::new(Pen {
+            color: (1.0, 1.0, 1.0),
+            width: 3.0,
+            state: PenState::Down,
+        }),
         vec![
             TurtleCommand::Repeat(8, vec![
                 TurtleCommand::Push,
@@ -231,7 +227,80 @@ r#"LSYSTEM (
     )
 );
 
-pub fn all_examples() -> [LSystemExample; 8] {
-    [ALGAE, KOCH, SIERPINSKI, TREE, DRAGON, PLANT, LEVY, GRAPES]
+pub const BUSH: LSystemExample = (
+    "bush",
+r#"LSYSTEM (
+    +A,
+    (A -> F[&A]///[&A]///[&A], F -> FF),
+    (F -> (MOVE 5),
+     A -> (MOVE 5),
+     + -> (TURN 90),
+     & -> (PITCH 30),
+     / -> (ROLL 40),
+     [ -> (PUSH),
+     ] -> (POP))
+)"#,
+    || LSystem::new(
+        "+A",
+        HashMap::from([
+            ('A', "F[&A]///[&A]///[&A]".into()),
+            ('F', "FF".into()),
+        ]),
+        HashMap::from([
+            ('F', vec![TurtleCommand::Move(5.0)]),
+            ('A', vec![TurtleCommand::Move(5.0)]),
+            ('+', vec![TurtleCommand::Turn(90.0)]),
+            ('&', vec![TurtleCommand::Pitch(30.0)]),
+            ('/', vec![TurtleCommand::Roll(40.0)]),
+            ('[', vec![TurtleCommand::Push]),
+            (']', vec![TurtleCommand::Pop]),
+        ]),
+    )
+);
+
+pub const LEAVES: LSystemExample = (
+    "leaves",
+r#"LSYSTEM (
+    ++X,
+    (X -> F[+L]F[-L]X),
+    (F -> (MOVE 10),
+     L -> (COLOR #33aa33,
+           POLYGON BEGIN,
+           VERTEX, TURN 30, MOVE 8,
+           VERTEX, TURN -60, MOVE 8,
+           VERTEX, TURN -120, MOVE 8,
+           VERTEX,
+           POLYGON END),
+     + -> (TURN 45),
+     - -> (TURN -45),
+     [ -> (PUSH),
+     ] -> (POP))
+)"#,
+    || LSystem::new(
+        "++X",
+        HashMap::from([
+            ('X', "F[+L]F[-L]X".into()),
+        ]),
+        HashMap::from([
+            ('F', vec![TurtleCommand::Move(10.0)]),
+            ('L', vec![
+                TurtleCommand::Color(0.2, 2.0 / 3.0, 0.2),
+                TurtleCommand::PolygonBegin,
+                TurtleCommand::Vertex, TurtleCommand::Turn(30.0), TurtleCommand::Move(8.0),
+                TurtleCommand::Vertex, TurtleCommand::Turn(-60.0), TurtleCommand::Move(8.0),
+                TurtleCommand::Vertex, TurtleCommand::Turn(-120.0), TurtleCommand::Move(8.0),
+                TurtleCommand::Vertex,
+                TurtleCommand::PolygonEnd,
+            ]),
+            ('+', vec![TurtleCommand::Turn(45.0)]),
+            ('-', vec![TurtleCommand::Turn(-45.0)]),
+            ('[', vec![TurtleCommand::Push]),
+            (']', vec![TurtleCommand::Pop]),
+        ]),
+    )
+);
+
+pub fn all_examples() -> [LSystemExample; 10] {
+    [ALGAE, KOCH, SIERPINSKI, TREE, DRAGON, PLANT, LEVY, GRAPES, BUSH, LEAVES]
 }
 
@@ -0,0 +1,138 @@
+use std::fmt::Write;
+
+use crate::render::*;
+
+// where (0, 0) is on the plotter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Origin {
+    // y grows up the page
+    BottomLeft,
+    // y grows down the page, like an AxiDraw
+    TopLeft,
+    // y grows up the page
+    Center,
+}
+
+#[derive(Clone, Debug)]
+pub struct GcodeOptions {
+    pub area: PlotArea,
+    pub origin: Origin,
+    // millimeters per minute while drawing and while moving between lines
+    pub feed_rate: f64,
+    pub travel_rate: f64,
+    // commands that lift and lower the pen
+    pub pen_up: String,
+    pub pen_down: String,
+    // digits after the decimal point in coordinates
+    pub precision: usize,
+}
+
+impl Default for GcodeOptions {
+    // a servo lifted pen
+    fn default() -> Self {
+        Self {
+            area: PlotArea::default(),
+            origin: Origin::BottomLeft,
+            feed_rate: 1500.0,
+            travel_rate: 3000.0,
+            pen_up: "M3 S0".to_string(),
+            pen_down: "M3 S90".to_string(),
+            precision: 3,
+        }
+    }
+}
+
+// every stroke and the outline of every fill, in the order they were drawn.
+// the pen is lifted for moves between lines and lowered to draw them
+pub fn gcode(geometry: &Geometry, options: &GcodeOptions) -> String {
+    let placement = options.area.placement(geometry, 1.0);
+    let (width, height) = (options.area.width, options.area.height);
+    let n = |v: f64| number(v, options.precision);
+    let place = |p: Point| {
+        let (x, y) = placement.place(p);
+        let (x, y) = match options.origin {
+            Origin::BottomLeft => (x, y),
+            Origin::TopLeft => (x, height - y),
+            Origin::Center => (x - width / 2.0, y - height / 2.0),
+        };
+        (n(x), n(y))
+    };
+
+    let mut result = String::new();
+    let _ = writeln!(result, "G21");
+    let _ = writeln!(result, "G90");
+    let _ = writeln!(result, "{}", options.pen_up);
+
+    let lines = pen_lines(geometry, place);
+    for (i, line) in lines.iter().enumerate() {
+        if line.lift {
+            if i > 0 {
+                let _ = writeln!(result, "{}", options.pen_up);
+            }
+            let (x, y) = &line.points[0];
+            let _ = writeln!(result, "G0 X{} Y{} F{}", x, y, n(options.travel_rate));
+            let _ = writeln!(result, "{}", options.pen_down);
+        }
+        for (x, y) in line.points[1..].iter() {
+            let _ = writeln!(result, "G1 X{} Y{} F{}", x, y, n(options.feed_rate));
+        }
+    }
+
+    if !lines.is_empty() {
+        let _ = writeln!(result, "{}", options.pen_up);
+    }
+    let _ = writeln!(result, "M2");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw::Viewport;
+    use crate::render::tests::drawing;
+
+    #[test]
+    fn moves() {
+        let options = GcodeOptions {
+            area: PlotArea { width: 100.0, height: 100.0, ..PlotArea::default() },
+            feed_rate: 1000.0,
+            travel_rate: 2000.0,
+            pen_up: "G0 Z5".to_string(),
+            pen_down: "G1 Z0".to_string(),
+            ..GcodeOptions::default()
+        };
+        assert_eq!(gcode(&drawing(), &options), [
+            "G21",
+            "G90",
+            "G0 Z5",
+            "G0 X0 Y25 F2000",
+            "G1 Z0",
+            "G1 X100 Y75 F1000",
+            "G0 Z5",
+            "G0 X0 Y25 F2000",
+            "G1 Z0",
+            "G1 X100 Y25 F1000",
+            "G1 X100 Y75 F1000",
+            "G1 X0 Y25 F1000",
+            "G1 X0 Y75 F1000",
+            "G0 Z5",
+            "M2",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn origins() {
+        let first_move = |origin: Origin| {
+            let options = GcodeOptions { area: PlotArea { width: 100.0, height: 100.0, ..PlotArea::default() }, origin, ..GcodeOptions::default() };
+            gcode(&drawing(), &options).lines().nth(3).unwrap().to_string()
+        };
+        assert_eq!(first_move(Origin::TopLeft), "G0 X0 Y75 F3000");
+        assert_eq!(first_move(Origin::Center), "G0 X-50 Y-25 F3000");
+
+        let viewport = Viewport { x0: -10.0, x1: 10.0, y0: -10.0, y1: 10.0 };
+        let area = PlotArea { viewport: Some(viewport), width: 100.0, height: 100.0 };
+        let options = GcodeOptions { area, ..GcodeOptions::default() };
+        assert_eq!(gcode(&drawing(), &options).lines().nth(3).unwrap(), "G0 X50 Y50 F3000");
+    }
+}
@@ -0,0 +1,110 @@
+use std::fmt::Write;
+
+use crate::render::*;
+
+// plotter units in a millimeter
+const UNITS: f64 = 40.0;
+
+#[derive(Clone, Debug)]
+pub struct HpglOptions {
+    pub area: PlotArea,
+    // how many pens the plotter holds. colors past that share pens
+    pub pens: usize,
+}
+
+impl Default for HpglOptions {
+    // an eight pen carousel
+    fn default() -> Self {
+        Self { area: PlotArea::default(), pens: 8 }
+    }
+}
+
+// the colors of the drawing in the order they are first used. the first
+// goes in pen 1, the next in pen 2 and so on, wrapping around the carousel
+pub fn pen_colors(geometry: &Geometry) -> Vec<&str> {
+    let mut result = Vec::new();
+    for (_, color) in outlines(geometry) {
+        if !result.contains(&color) {
+            result.push(color);
+        }
+    }
+    result
+}
+
+// every stroke and the outline of every fill, in the order they were drawn,
+// with a pen per color
+pub fn hpgl(geometry: &Geometry, options: &HpglOptions) -> String {
+    let placement = options.area.placement(geometry, UNITS);
+    let colors = pen_colors(geometry);
+    let pens = options.pens.max(1);
+
+    let mut result = String::new();
+    result.push_str("IN;\n");
+    let mut pen = None;
+    let lines = pen_lines(geometry, |p| {
+        let (x, y) = placement.place(p);
+        (x.round() as i64, y.round() as i64)
+    });
+    for line in lines.iter() {
+        let number = colors.iter().position(|c| *c == line.color).unwrap() % pens + 1;
+        // a new pen starts where the line does
+        let changed = pen != Some(number);
+        if changed {
+            let _ = writeln!(result, "SP{};", number);
+            pen = Some(number);
+        }
+        if line.lift || changed {
+            let (x, y) = line.points[0];
+            let _ = writeln!(result, "PU{},{};", x, y);
+        }
+        if line.points.len() > 1 {
+            let coordinates: Vec<String> = line.points[1..].iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+            let _ = writeln!(result, "PD{};", coordinates.join(","));
+        }
+    }
+    if pen.is_some() {
+        result.push_str("PU;\n");
+    }
+    result.push_str("SP0;\n");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw::Viewport;
+    use crate::render::tests::drawing;
+
+    #[test]
+    fn pens() {
+        assert_eq!(pen_colors(&drawing()), vec!["#ff0000", "#0000ff", "#00ff00"]);
+
+        let area = PlotArea { width: 100.0, height: 100.0, ..PlotArea::default() };
+        let options = HpglOptions { area, pens: 2 };
+        assert_eq!(hpgl(&drawing(), &options), [
+            "IN;",
+            "SP1;",
+            "PU0,1000;",
+            "PD4000,3000;",
+            "SP2;",
+            "PU0,1000;",
+            "PD4000,1000,4000,3000,0,1000;",
+            "SP1;",
+            "PU0,1000;",
+            "PD0,3000;",
+            "PU;",
+            "SP0;",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn scaling() {
+        let viewport = Viewport { x0: -10.0, x1: 10.0, y0: -10.0, y1: 10.0 };
+        let area = PlotArea { viewport: Some(viewport), width: 100.0, height: 50.0 };
+        let actual = hpgl(&drawing(), &HpglOptions { area, ..HpglOptions::default() });
+        // 20 units across the 50 mm side, centered on the 100 mm one
+        assert_eq!(actual.lines().nth(2), Some("PU2000,1000;"));
+        assert_eq!(actual.lines().nth(3), Some("PD3000,1500;"));
+    }
+}
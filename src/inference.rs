@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::l_system::*;
+
+// upper bound on the number of successor candidates tried before giving up
+const MAX_STEPS: usize = 1_000_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InferenceError {
+    // at least two generations are needed to say anything about the rules
+    TooFewGenerations(usize),
+    // an empty word can only derive an empty word
+    EmptyWord { generation: usize },
+    // no deterministic context free rule set derives `generation + 1` from
+    // `generation`, given whatever was needed to derive the earlier ones
+    NoRules { generation: usize },
+    SearchLimitExceeded,
+}
+
+impl fmt::Display for InferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InferenceError::TooFewGenerations(n) =>
+                write!(f, "need at least 2 generations, got {}", n),
+            InferenceError::EmptyWord { generation } =>
+                write!(f, "generation {} is empty but generation {} is not", generation, generation + 1),
+            InferenceError::NoRules { generation } =>
+                write!(f, "no rules derive generation {} from generation {}", generation + 1, generation),
+            InferenceError::SearchLimitExceeded =>
+                write!(f, "gave up after trying {} candidate rules", MAX_STEPS),
+        }
+    }
+}
+
+struct Search {
+    words: Vec<Vec<char>>,
+    rules: HashMap<char, Vec<char>>,
+    steps: usize,
+    deepest: usize,
+}
+
+enum Outcome { Found, NotFound, GaveUp }
+
+impl Search {
+    // tries to derive words[generation + 1][offset..] from
+    // words[generation][position..], extending the rules as needed
+    fn solve(&mut self, generation: usize, position: usize, offset: usize) -> Outcome {
+        self.deepest = self.deepest.max(generation);
+        if generation + 1 == self.words.len() {
+            return Outcome::Found;
+        }
+        let (word, target) = (&self.words[generation], &self.words[generation + 1]);
+        if position == word.len() {
+            return if offset == target.len() {
+                self.solve(generation + 1, 0, 0)
+            } else {
+                Outcome::NotFound
+            };
+        }
+
+        let c = word[position];
+        if let Some(successor) = self.rules.get(&c) {
+            let end = offset + successor.len();
+            if end <= target.len() && target[offset..end] == successor[..] {
+                return self.solve(generation, position + 1, end);
+            }
+            return Outcome::NotFound;
+        }
+
+        // the rest of the word needs at least this much of the target
+        let reserved: usize = word[position + 1..].iter()
+            .map(|d| self.rules.get(d).map(|s| s.len()).unwrap_or(0))
+            .sum();
+        let available = match (target.len() - offset).checked_sub(reserved) {
+            Some(n) => n,
+            None => return Outcome::NotFound,
+        };
+
+        for length in 0..=available {
+            self.steps += 1;
+            if self.steps > MAX_STEPS {
+                return Outcome::GaveUp;
+            }
+            let successor = self.words[generation + 1][offset..offset + length].to_vec();
+            self.rules.insert(c, successor);
+            match self.solve(generation, position + 1, offset + length) {
+                Outcome::NotFound => {}
+                outcome => return outcome,
+            }
+            self.rules.remove(&c);
+        }
+        Outcome::NotFound
+    }
+}
+
+// finds a deterministic context free rule set under which each word derives
+// the next one. symbols whose successor is never observed are left without a
+// rule, which makes them constants. the result has an empty interpreter
+pub fn infer(generations: &[&str]) -> Result<LSystem, InferenceError> {
+    if generations.len() < 2 {
+        return Err(InferenceError::TooFewGenerations(generations.len()));
+    }
+    for (generation, pair) in generations.windows(2).enumerate() {
+        if pair[0].is_empty() && !pair[1].is_empty() {
+            return Err(InferenceError::EmptyWord { generation });
+        }
+    }
+
+    let mut search = Search {
+        words: generations.iter().map(|w| w.chars().collect()).collect(),
+        rules: HashMap::new(),
+        steps: 0,
+        deepest: 0,
+    };
+    match search.solve(0, 0, 0) {
+        Outcome::Found => {
+            let rules = search.rules.into_iter()
+                .filter(|(c, successor)| successor[..] != [*c])
+                .map(|(c, successor)| (c, successor.into_iter().collect()))
+                .collect();
+            Ok(LSystem::new(generations[0], rules, HashMap::new()))
+        }
+        Outcome::NotFound => Err(InferenceError::NoRules { generation: search.deepest }),
+        Outcome::GaveUp => Err(InferenceError::SearchLimitExceeded),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::examples::*;
+
+    fn words(lsystem: &LSystem, n: usize) -> Vec<String> {
+        lsystem.generations().take(n).map(|g| g.word).collect()
+    }
+
+    #[test]
+    fn algae() {
+        let observed = words(&ALGAE.2(), 6);
+        let observed: Vec<&str> = observed.iter().map(|w| w.as_str()).collect();
+        let inferred = infer(&observed).unwrap();
+        assert_eq!(inferred.expand(8), ALGAE.2().expand(8));
+    }
+
+    #[test]
+    fn reproduces_examples() {
+        for (name, _, lsystem) in [KOCH, DRAGON, PLANT, TREE] {
+            let lsystem = lsystem();
+            let observed = words(&lsystem, 4);
+            let observed: Vec<&str> = observed.iter().map(|w| w.as_str()).collect();
+            let inferred = infer(&observed).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!(inferred.equivalent_up_to(&lsystem, 3), Ok(()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn erasing_rules() {
+        let inferred = infer(&["AB", "A", "A"]).unwrap();
+        assert_eq!(inferred.expand(1), "A");
+    }
+
+    #[test]
+    fn no_rules() {
+        assert_eq!(infer(&["A", "AB", "BA"]), Err(InferenceError::NoRules { generation: 1 }));
+        assert_eq!(infer(&["AA", "ABA"]), Err(InferenceError::NoRules { generation: 0 }));
+    }
+
+    #[test]
+    fn bad_input() {
+        assert_eq!(infer(&["A"]), Err(InferenceError::TooFewGenerations(1)));
+        assert_eq!(infer(&["A", "", "B"]), Err(InferenceError::EmptyWord { generation: 1 }));
+    }
+}
@@ -12,13 +12,27 @@ use crate::turtle::*;
 type CharStream = Pin<Box<dyn Stream<Item=char>>>;
 type TurtleCommandStream = Pin<Box<dyn Stream<Item=TurtleCommand>>>;
 
-fn string_to_stream(s: &String) -> CharStream {
+fn string_to_stream(s: &str) -> CharStream {
     let v: Vec<char> = s.chars().collect();
     Box::pin(stream::iter(v))
 }
 
+#[cfg(feature = "serde")]
+fn serialize_sorted<S, V>(map: &HashMap<char, V>, serializer: S) -> Result<S::Ok, S::Error>
+where S: serde::Serializer, V: serde::Serialize {
+    use serde::Serialize;
+    // keys are written as strings since some formats (toml) only allow those
+    map.iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect::<std::collections::BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 struct Rules {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_sorted"))]
     inner: HashMap<char, String>
 }
 
@@ -28,11 +42,18 @@ impl Rules {
     }
 
     fn get_as_string(&self, c: char) -> String {
-        self.inner.get(&c)
-            .map(|s| s.clone())
+        self.inner.get(&c).cloned()
             .unwrap_or(format!("{}", c))
     }
 
+    fn apply(&self, s: &str) -> String {
+        let mut result = String::new();
+        for c in s.chars() {
+            result.push_str(&self.get_as_string(c))
+        }
+        result
+    }
+
     fn get_as_stream(&self, c: char) -> CharStream { 
         self.inner.get(&c)
             .map(|s| string_to_stream(s))
@@ -41,7 +62,10 @@ impl Rules {
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 struct Interpreter {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_sorted"))]
     inner: HashMap<char, Vec<TurtleCommand>>,
 }
 
@@ -55,7 +79,7 @@ impl Interpreter {
     }
 
     fn get_as_stream(&self, c: &char) -> TurtleCommandStream {
-        if let Some(r) = self.inner.get(&c) {
+        if let Some(r) = self.inner.get(c) {
             Box::pin(stream::iter(r.clone()))
         } else {
             Box::pin(stream::empty())
@@ -64,10 +88,17 @@ impl Interpreter {
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LSystem {
     start: String,
     rules: Rc<Rules>, 
     interpreter: Rc<Interpreter>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    palette: Vec<Color>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    tropism: Option<Tropism>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    jitter: Option<Jitter>,
 }
 
 impl LSystem {
@@ -78,30 +109,70 @@ impl LSystem {
             start: start.to_owned(),
             rules: Rc::new(Rules::from(rules)),
             interpreter: Rc::new(Interpreter::from(interpreter)),
+            palette: Vec::new(),
+            tropism: None,
+            jitter: None,
         }
     }
 
+    pub fn with_palette(mut self, palette: Vec<Color>) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    pub fn with_tropism(mut self, tropism: Tropism) -> Self {
+        self.tropism = Some(tropism);
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = Some(jitter);
+        self
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json(input: &str) -> Result<Self, String> {
+        serde_json::from_str(input).map_err(|err| format!("json error: {}", err))
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|err| format!("json error: {}", err))
+    }
+
+    pub fn start(&self) -> &str {
+        &self.start
+    }
+
+    pub(crate) fn rules(&self) -> &HashMap<char, String> {
+        &self.rules.inner
+    }
+
+    // iterations count rewriting steps: generation 0 is the start value and
+    // `expand(n)` is the word after the rules have been applied n times. the
+    // same numbering is used by `expand_stream`, `compile`, `compile_stream`
+    // and the `index` of the items yielded by `generations`
     pub fn expand(&self, iterations: u32) -> String {
         let mut s = self.start.clone();
-        for _ in 1..iterations {
-            s = self.apply_rules(s);
+        for _ in 0..iterations {
+            s = self.rules.apply(&s);
         }
         s
     }
 
-    fn apply_rules(&self, s: String) -> String {
-        let mut result = String::new();
-        for c in s.chars() {
-            result.push_str(&self.rules.get_as_string(c))
+    pub fn generations(&self) -> Generations {
+        Generations {
+            rules: self.rules.clone(),
+            current: None,
+            pending: Some(self.start.clone()),
         }
-        result
     }
 
     pub fn expand_stream(&self, iterations: u32) -> CharStream {
         fn aux(rules: Rc<Rules>,
                input: CharStream,
                iterations: u32) -> CharStream {
-            if iterations <= 1 {
+            if iterations == 0 {
                 input
             } else {
                 Box::pin(input.flat_map(move |c| {
@@ -113,27 +184,57 @@ impl LSystem {
         aux(self.rules.clone(), string_to_stream(&self.start), iterations)
     }
 
+    // compares the words of generations 0 through `iterations` symbol by
+    // symbol. the words are walked lazily and never built in full
+    pub fn equivalent_up_to(&self, other: &LSystem, iterations: u32) -> Result<(), Divergence<char>> {
+        for generation in 0..=iterations {
+            compare(generation, self.derivation(generation), other.derivation(generation))?;
+        }
+        Ok(())
+    }
+
+    // like `equivalent_up_to` but compares the turtle commands that each
+    // generation compiles to, so systems that use different symbols for the
+    // same drawing are equivalent
+    pub fn programs_equivalent_up_to(&self, other: &LSystem, iterations: u32) -> Result<(), Divergence<TurtleCommand>> {
+        fn commands<'a>(lsystem: &'a LSystem, generation: u32) -> impl Iterator<Item=TurtleCommand> + 'a {
+            lsystem.derivation(generation).flat_map(move |c| {
+                lsystem.interpreter.get(&c).cloned().unwrap_or_default()
+            })
+        }
+        for generation in 0..=iterations {
+            compare(generation, commands(self, generation), commands(other, generation))?;
+        }
+        Ok(())
+    }
+
+    fn derivation(&self, iterations: u32) -> Derivation<'_> {
+        Derivation {
+            rules: &self.rules,
+            stack: vec![(iterations, self.start.chars())],
+        }
+    }
+
     pub fn compile(&self, iterations: u32) -> TurtleProgram {
         let mut commands = Vec::new();
+        let mut symbols = Vec::new();
         let s = self.expand(iterations);
         for c in s.chars() {
             if let Some(r) = self.interpreter.get(&c) {
                 commands.append(&mut r.clone());
+                symbols.resize(commands.len(), c);
             }
         }
 
-        TurtleProgram::new(
-            Turtle {
-                location: (0.0, 0.0),
-                orientation: 0.0,
-                pen: Pen {
-                    color: (1.0, 1.0, 1.0),
-                    width: 2.0,
-                    state: PenState::Down
-                },
-            },
+        self.declare(TurtleProgram::new(
+            Turtle::new(Pen {
+                color: (1.0, 1.0, 1.0),
+                width: 2.0,
+                state: PenState::Down
+            }),
             commands,
-        )
+        ))
+        .with_symbols(symbols)
     }
 
 
@@ -141,21 +242,125 @@ impl LSystem {
         let interpreter = self.interpreter.clone();
 
         let commands = Box::pin(self.expand_stream(iterations).flat_map(move |c| {
-            interpreter.get_as_stream(&c)
+            interpreter.get_as_stream(&c).map(move |command| (command, c))
         }));
 
-        TurtleProgram::new_async(
-            Turtle {
-                location: (0.0, 0.0),
-                orientation: 0.0,
-                pen: Pen {
-                    color: (1.0, 1.0, 1.0),
-                    width: 2.0,
-                    state: PenState::Down
-                },
-            },
+        self.declare(TurtleProgram::new_async_with_symbols(
+            Turtle::new(Pen {
+                color: (1.0, 1.0, 1.0),
+                width: 2.0,
+                state: PenState::Down
+            }),
             commands
-        )
+        ))
+    }
+
+    // the palette, tropism and jitter declared for the system
+    fn declare(&self, mut program: TurtleProgram) -> TurtleProgram {
+        program = program.with_palette(self.palette.clone());
+        if let Some(tropism) = self.tropism {
+            program = program.with_tropism(tropism);
+        }
+        if let Some(jitter) = self.jitter {
+            program = program.with_jitter(jitter);
+        }
+        program
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Generation {
+    pub index: u32,
+    pub word: String,
+    pub length: usize,
+    pub counts: HashMap<char, usize>,
+    // length relative to the previous generation. `None` for generation 0
+    // and whenever the previous word was empty
+    pub growth: Option<f64>,
+}
+
+pub struct Generations {
+    rules: Rc<Rules>,
+    current: Option<(u32, String)>,
+    pending: Option<String>,
+}
+
+impl Iterator for Generations {
+    type Item = Generation;
+
+    fn next(&mut self) -> Option<Generation> {
+        let previous = self.current.take();
+        let (index, word) = match previous {
+            None => (0, self.pending.take()?),
+            Some((index, ref word)) => (index.checked_add(1)?, self.rules.apply(word)),
+        };
+
+        let mut counts = HashMap::new();
+        for c in word.chars() {
+            *counts.entry(c).or_insert(0) += 1;
+        }
+        let length = word.chars().count();
+        let growth = previous
+            .map(|(_, previous)| previous.chars().count())
+            .filter(|previous| *previous > 0)
+            .map(|previous| length as f64 / previous as f64);
+
+        self.current = Some((index, word.clone()));
+        Some(Generation { index, word, length, counts, growth })
+    }
+}
+
+// the first position at which two derivations differ. `None` means that
+// side's sequence ended early
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence<T> {
+    pub generation: u32,
+    pub index: usize,
+    pub left: Option<T>,
+    pub right: Option<T>,
+}
+
+fn compare<T: PartialEq>(generation: u32,
+                         left: impl Iterator<Item=T>,
+                         right: impl Iterator<Item=T>) -> Result<(), Divergence<T>> {
+    let mut left = left.fuse();
+    let mut right = right.fuse();
+    let mut index = 0;
+    loop {
+        match (left.next(), right.next()) {
+            (None, None) => return Ok(()),
+            (l, r) if l == r => index += 1,
+            (left, right) => return Err(Divergence { generation, index, left, right }),
+        }
+    }
+}
+
+// yields the word of a generation one symbol at a time. each stack entry is
+// the remainder of a successor together with how many more times its symbols
+// still have to be rewritten
+struct Derivation<'a> {
+    rules: &'a Rules,
+    stack: Vec<(u32, std::str::Chars<'a>)>,
+}
+
+impl Iterator for Derivation<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        loop {
+            let (depth, chars) = self.stack.last_mut()?;
+            let depth = *depth;
+            match chars.next() {
+                None => {
+                    self.stack.pop();
+                }
+                Some(c) if depth == 0 => return Some(c),
+                Some(c) => match self.rules.inner.get(&c) {
+                    Some(successor) => self.stack.push((depth - 1, successor.chars())),
+                    None => return Some(c),
+                },
+            }
+        }
     }
 }
 
@@ -163,34 +368,169 @@ impl LSystem {
 mod tests {
     use super::*;
     use std::collections::HashMap;
-    use wasm_bindgen_test::*;
-    use wasm_bindgen_futures;
 
-    #[wasm_bindgen_test]
-    async fn expand_stream_1() {
+    #[test]
+    fn expand_stream_1() {
         let system = LSystem::new("A", HashMap::from([('A', "AB".into())]), HashMap::new());
-        let s = system.expand_stream(1);
-        let v: Vec<char> = s.collect().await;
+        let s = system.expand_stream(0);
+        let v: Vec<char> = futures::executor::block_on(s.collect());
         assert_eq!(v, vec!['A'])
     }
 
-    #[wasm_bindgen_test]
-    async fn expand_stream_2() {
+    #[test]
+    fn expand_stream_2() {
         let system = LSystem::new("A", HashMap::from([('A', "AB".into())]), HashMap::new());
-        let s = system.expand_stream(2);
-        let v: Vec<char> = s.collect().await;
+        let s = system.expand_stream(1);
+        let v: Vec<char> = futures::executor::block_on(s.collect());
         assert_eq!(v, vec!['A', 'B'])
     }
 
-    #[wasm_bindgen_test]
-    async fn expand_stream_3() {
+    #[test]
+    fn expand_stream_3() {
         let system = LSystem::new(
             "AA",
             HashMap::from([('A', "BB".into()), ('B', "A".into())]),
             HashMap::new()
         );
-        let s = system.expand_stream(3);
-        let v: Vec<char> = s.collect().await;
+        let s = system.expand_stream(2);
+        let v: Vec<char> = futures::executor::block_on(s.collect());
         assert_eq!(v, vec!['A', 'A', 'A', 'A'])
     }
+
+    #[test]
+    fn expand_counts_rewriting_steps() {
+        let system = LSystem::new("A", HashMap::from([('A', "AB".into()), ('B', "A".into())]), HashMap::new());
+        assert_eq!(system.expand(0), "A");
+        assert_eq!(system.expand(1), "AB");
+        assert_eq!(system.expand(4), "ABAABABA");
+    }
+
+    #[test]
+    fn generations_match_expand() {
+        let system = LSystem::new("A", HashMap::from([('A', "AB".into()), ('B', "A".into())]), HashMap::new());
+        for generation in system.generations().take(8) {
+            assert_eq!(generation.word, system.expand(generation.index));
+        }
+    }
+
+    #[test]
+    fn generations_stats() {
+        let system = LSystem::new("A", HashMap::from([('A', "AB".into()), ('B', "A".into())]), HashMap::new());
+        let v: Vec<Generation> = system.generations().take(5).collect();
+        assert_eq!(v[0].growth, None);
+        assert_eq!(v[4].word, "ABAABABA");
+        assert_eq!(v[4].length, 8);
+        assert_eq!(v[4].counts, HashMap::from([('A', 5), ('B', 3)]));
+        assert_eq!(v[4].growth, Some(8.0 / 5.0));
+    }
+
+    #[test]
+    fn derivation_matches_expand() {
+        let system = crate::examples::PLANT.2();
+        for n in 0..5 {
+            assert_eq!(system.derivation(n).collect::<String>(), system.expand(n));
+        }
+    }
+
+    #[test]
+    fn equivalent_systems() {
+        let a = LSystem::new("A", HashMap::from([('A', "AB".into()), ('B', "A".into())]), HashMap::new());
+        let b = LSystem::new("A", HashMap::from([('A', "AB".into()), ('B', "A".into()), ('C', "CC".into())]), HashMap::new());
+        assert_eq!(a.equivalent_up_to(&b, 10), Ok(()));
+    }
+
+    #[test]
+    fn divergent_systems() {
+        let a = LSystem::new("A", HashMap::from([('A', "AB".into()), ('B', "A".into())]), HashMap::new());
+        let b = LSystem::new("A", HashMap::from([('A', "AB".into()), ('B', "AA".into())]), HashMap::new());
+        assert_eq!(
+            a.equivalent_up_to(&b, 10),
+            Err(Divergence { generation: 2, index: 3, left: None, right: Some('A') })
+        );
+    }
+
+    #[test]
+    fn equivalent_programs() {
+        let a = LSystem::new(
+            "F",
+            HashMap::from([('F', "F+F".into())]),
+            HashMap::from([('F', vec![TurtleCommand::Move(10.0)]), ('+', vec![TurtleCommand::Turn(90.0)])]),
+        );
+        let b = LSystem::new(
+            "G",
+            HashMap::from([('G', "G-G".into())]),
+            HashMap::from([('G', vec![TurtleCommand::Move(10.0)]), ('-', vec![TurtleCommand::Turn(90.0)])]),
+        );
+        assert!(a.equivalent_up_to(&b, 3).is_err());
+        assert_eq!(a.programs_equivalent_up_to(&b, 6), Ok(()));
+    }
+
+    #[test]
+    fn segments() {
+        let lsystem = LSystem::new(
+            "F[+G]F",
+            HashMap::new(),
+            HashMap::from([
+                ('F', vec![TurtleCommand::Move(10.0)]),
+                ('G', vec![TurtleCommand::Turn(-10.0), TurtleCommand::Move(5.0)]),
+                ('+', vec![TurtleCommand::Turn(90.0)]),
+                ('[', vec![TurtleCommand::Push]),
+                (']', vec![TurtleCommand::Pop]),
+            ]),
+        );
+        let summary = |segments: Vec<Segment>| -> Vec<(usize, usize, Option<char>)> {
+            segments.iter().map(|s| (s.depth, s.command, s.symbol)).collect()
+        };
+        let segments = lsystem.compile(0).segments().unwrap();
+        assert_eq!(summary(segments.clone()), vec![(0, 0, Some('F')), (1, 4, Some('G')), (0, 6, Some('F'))]);
+        assert_eq!((segments[1].width, segments[1].color), (2.0, (1.0, 1.0, 1.0)));
+
+        let segments = lsystem.compile_stream(0).segments().unwrap();
+        assert_eq!(summary(segments), vec![(0, 0, Some('F')), (1, 4, Some('G')), (0, 6, Some('F'))]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        for (name, _, lsystem) in crate::examples::all_examples() {
+            let json = lsystem().to_json().unwrap();
+            assert_eq!(LSystem::from_json(&json), Ok(lsystem()), "{}", name);
+            assert_eq!(lsystem().to_json().unwrap(), json, "{}", name);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_representation() {
+        let json = LSystem::from_json(r#"{
+            "start": "F",
+            "rules": { "F": "F+F" },
+            "interpreter": { "F": [{ "move": 10.0 }], "+": [{ "turn": 90.0 }, "push"] }
+        }"#);
+        let expected = LSystem::new(
+            "F",
+            HashMap::from([('F', "F+F".into())]),
+            HashMap::from([
+                ('F', vec![TurtleCommand::Move(10.0)]),
+                ('+', vec![TurtleCommand::Turn(90.0), TurtleCommand::Push]),
+            ]),
+        );
+        assert_eq!(json, Ok(expected));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn toml_round_trip() {
+        let lsystem = crate::examples::GRAPES.2();
+        let source = toml::to_string(&lsystem).unwrap();
+        assert_eq!(toml::from_str::<LSystem>(&source).unwrap(), lsystem);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn program_json_round_trip() {
+        let json = crate::examples::PLANT.2().compile(2).to_json().unwrap();
+        assert_eq!(TurtleProgram::from_json(&json).unwrap().to_json().unwrap(), json);
+        assert!(crate::examples::PLANT.2().compile_stream(2).to_json().is_err());
+    }
 }
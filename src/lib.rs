@@ -1,17 +1,33 @@
-mod draw;
+mod analysis;
+pub mod draw;
+pub mod dxf;
 mod examples;
-mod l_system;
+pub mod gcode;
+pub mod hpgl;
+pub mod inference;
+pub mod l_system;
+pub mod mesh;
 mod parser;
-mod turtle;
+pub mod print;
+pub mod random;
+pub mod raster;
+pub mod render;
+pub mod svg;
+pub mod turtle;
 mod util;
+pub mod vector;
 
 use std::{cell::RefCell, rc::Rc};
 use wasm_bindgen::prelude::*;
 use web_sys::{Event};
 
+use analysis::{analyze, max_iterations};
 use draw::*;
 use examples::all_examples;
 use parser::parse;
+use render::{Canvas, Geometry, RenderTarget};
+use svg::{svg, SvgOptions};
+use turtle::{Projection, TurtleProgram};
 use util::*;
 
 #[wasm_bindgen]
@@ -23,11 +39,18 @@ pub fn examples() -> js_sys::Map {
     result
 }
 
+// pixels left around the figure when it is fit to the canvas
+const MARGIN: f64 = 20.0;
+
 #[wasm_bindgen]
 struct State {
     program: Option<String>,
     iterations: u32,
     viewport: Viewport,
+    projection: Projection,
+    // the margin to fit the viewport to the drawing with, the next time it
+    // is drawn
+    fit: Option<f64>,
 }
 
 #[wasm_bindgen]
@@ -48,8 +71,10 @@ pub fn init() -> Controller {
 
     let state = Rc::new(RefCell::new(State {
         program: None,
-        iterations: 10,
-        viewport: viewport,
+        iterations: 9,
+        viewport,
+        projection: Projection::default(),
+        fit: None,
     }));
 
     let handle_resize = {
@@ -60,10 +85,11 @@ pub fn init() -> Controller {
             canvas.set_height(canvas.client_height() as u32);
             let (width, height) = (canvas.width() as f64, canvas.height() as f64);
             let ratio = height / width;
-            let Viewport { x0, x1, .. } = state.borrow().viewport.clone();
-            let viewport = Viewport { x0, x1, y0: x0 * ratio, y1: x1 * ratio };
+            let Viewport { x0, x1, y0, y1 } = state.borrow().viewport;
+            let (center, half_height) = ((y0 + y1) / 2.0, (x1 - x0) / 2.0 * ratio);
+            let viewport = Viewport { x0, x1, y0: center - half_height, y1: center + half_height };
             state.borrow_mut().viewport = viewport;
-            let _ = state.borrow().draw();
+            let _ = state.borrow_mut().draw();
         })
     };
     let window = web_sys::window().expect("no window?!");
@@ -76,28 +102,83 @@ pub fn init() -> Controller {
 }
 
 impl State {
-    fn draw(&self) -> Result<(), JsValue> {
-        let program = self.program.clone();
-        let iterations = self.iterations;
-        let viewport = self.viewport.clone();
-
-        if let Some(input) = program {
-            match parse(&input) {
-                Ok(lsystem) => {
-                    let program =
-                        if false { lsystem.compile_stream(iterations) }
-                        else { lsystem.compile(iterations) };
-                    let context = get_context2d();
-                    clear_canvas(&context);
-                    program.execute(context, viewport);
-                    return Ok(());
-                }
-                Err(err) => {
-                    return Err(err.into());
-                }
+    fn program(&self) -> Result<TurtleProgram, JsValue> {
+        match &self.program {
+            Some(input) => {
+                let lsystem = parse(input)?;
+                let program =
+                    if false { lsystem.compile_stream(self.iterations) }
+                    else { lsystem.compile(self.iterations) };
+                Ok(program.with_projection(self.projection))
+            }
+            None => Err("program is not set".into()),
+        }
+    }
+
+    fn draw_commands(&self) -> Result<Vec<DrawCommand>, JsValue> {
+        self.program()?.draw_commands().map_err(|err| err.to_string().into())
+    }
+
+    // runs the program once, fitting the viewport to what it draws first
+    // when a fit is pending
+    fn draw(&mut self) -> Result<(), JsValue> {
+        let commands = self.draw_commands()?;
+        if let Some(margin) = self.fit.take() {
+            let mut geometry = Geometry::new();
+            geometry.render_all(&commands);
+            self.fit_to(&geometry, margin);
+        }
+        let context = get_context2d();
+        clear_canvas(&context);
+        Canvas::new(context, self.viewport).render_all(&commands);
+        Ok(())
+    }
+
+    fn geometry(&self) -> Result<Geometry, JsValue> {
+        let mut geometry = Geometry::new();
+        geometry.render_all(&self.draw_commands()?);
+        Ok(geometry)
+    }
+
+    // centers the figure on the canvas, as large as it fits. the viewport
+    // is left alone when nothing is drawn
+    fn fit_to(&mut self, geometry: &Geometry, margin: f64) {
+        if let Some(bounds) = geometry.bounds() {
+            let canvas = get_context2d().canvas().expect("canvas missing!");
+            self.viewport = Viewport::fit(bounds, canvas.width() as f64, canvas.height() as f64, margin);
+        }
+    }
+
+    // what the canvas shows, as an svg document
+    fn svg(&self) -> Result<String, JsValue> {
+        let geometry = self.geometry()?;
+        let canvas = get_context2d().canvas().expect("canvas missing!");
+        let options = SvgOptions {
+            viewport: Some(self.viewport),
+            size: Some((canvas.width() as f64, canvas.height() as f64)),
+            ..SvgOptions::default()
+        };
+        Ok(svg(&geometry, &options))
+    }
+
+    fn growth(&self) -> Result<String, JsValue> {
+        match &self.program {
+            Some(input) => {
+                let lsystem = parse(input)?;
+                Ok(analyze(&lsystem).class.to_string())
             }
+            None => Err("program is not set".into()),
+        }
+    }
+
+    fn max_iterations(&self, max_length: u32) -> Result<u32, JsValue> {
+        match &self.program {
+            Some(input) => {
+                let lsystem = parse(input)?;
+                Ok(max_iterations(&lsystem, max_length as u64, 100))
+            }
+            None => Err("program is not set".into()),
         }
-        Err("program is not set".into())
     }
 
     fn zoom(&mut self, multiplier: f64) {
@@ -105,7 +186,7 @@ impl State {
         let (w, h) = (canvas.client_width() as f64, canvas.client_height() as f64);
         let ratio = h / w;
 
-        let Viewport { x0, x1, y0, y1 } = self.viewport.clone();
+        let Viewport { x0, x1, y0, y1 } = self.viewport;
 
         let center_x = (x0 + x1) / 2.0;
         let center_y = (y0 + y1) / 2.0;
@@ -124,20 +205,47 @@ impl State {
 
 #[wasm_bindgen]
 impl Controller {
+    // a program that differs from the current one is fit to the canvas
+    // when it is next drawn
     pub fn set_program(&self, program: String) {
-        self.state.borrow_mut().program = Some(program);
+        let mut state = self.state.borrow_mut();
+        if state.program.as_ref() != Some(&program) {
+            state.program = Some(program);
+            state.fit = Some(MARGIN);
+        }
     }
 
     pub fn set_iterations(&self, iterations: u32) {
         self.state.borrow_mut().iterations = iterations;
     }
 
+    pub fn set_projection(&self, azimuth: f64, elevation: f64) {
+        self.state.borrow_mut().projection = Projection { azimuth, elevation };
+    }
+
     pub fn zoom(&self, multiplier: f64) {
         self.state.borrow_mut().zoom(multiplier);
     }
 
+    // fits the viewport to the drawing when it is next drawn
+    pub fn fit_to_content(&self, margin: f64) {
+        self.state.borrow_mut().fit = Some(margin);
+    }
+
     pub fn draw(&self) -> Result<(), JsValue> {
-        self.state.borrow().draw()
+        self.state.borrow_mut().draw()
+    }
+
+    pub fn growth(&self) -> Result<String, JsValue> {
+        self.state.borrow().growth()
+    }
+
+    pub fn svg(&self) -> Result<String, JsValue> {
+        self.state.borrow().svg()
+    }
+
+    pub fn max_iterations(&self, max_length: u32) -> Result<u32, JsValue> {
+        self.state.borrow().max_iterations(max_length)
     }
 }
 
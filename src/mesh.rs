@@ -0,0 +1,385 @@
+use std::fmt::Write;
+
+use crate::render::number;
+use crate::turtle::*;
+use crate::vector::*;
+
+#[derive(Clone, Debug)]
+pub struct MeshOptions {
+    // corners around each tube
+    pub sides: usize,
+    // tube radii are half the pen width times this
+    pub radius: f64,
+}
+
+impl Default for MeshOptions {
+    fn default() -> Self {
+        Self { sides: 8, radius: 1.0 }
+    }
+}
+
+// triangles with a color at every vertex, in turtle space. each branch of
+// the model is swept into a closed tube and each polygon becomes a fan of
+// triangles facing the way its vertices turn
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mesh {
+    pub vertices: Vec<Vec3>,
+    // between 0 and 1, whatever the pen held
+    pub colors: Vec<Color>,
+    // counter clockwise seen from outside
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl Mesh {
+    pub fn new(model: &Model, options: &MeshOptions) -> Self {
+        let mut mesh = Self::default();
+        for branch in branches(&model.segments) {
+            mesh.tube(branch, options.sides.max(3), branch[0].width / 2.0 * options.radius);
+        }
+        for polygon in model.polygons.iter() {
+            mesh.face(polygon);
+        }
+        mesh
+    }
+
+    fn vertex(&mut self, point: Vec3, color: Color) -> u32 {
+        let (r, g, b) = color;
+        self.vertices.push(point);
+        self.colors.push((r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0)));
+        self.vertices.len() as u32 - 1
+    }
+
+    // a generalized cylinder along a branch. consecutive segments share the
+    // ring where they meet, mitered so the tube keeps its thickness through
+    // the bend, and the branch is capped at both ends
+    fn tube(&mut self, branch: &[Segment], sides: usize, radius: f64) {
+        if radius <= 0.0 {
+            return;
+        }
+        let directions: Vec<Vec3> = branch.iter().map(|segment| (segment.end - segment.start).normalize()).collect();
+        // a right handed frame with w along the first segment, carried along
+        // the branch so the tube doesn't twist
+        let w = directions[0];
+        let mut u = w.cross(if w.x.abs() < 0.9 { Vec3::X } else { Vec3::Y }).normalize();
+
+        let first = self.vertices.len() as u32;
+        for (i, segment) in branch.iter().enumerate() {
+            let w = directions[i];
+            let v = w.cross(u);
+            // the ring at the start of the segment is pushed along it onto
+            // the plane that halves the bend from the segment before
+            let normal = if i == 0 { w } else { (directions[i - 1] + w).normalize() };
+            let miter = w.dot(normal);
+            for k in 0..sides {
+                let (sin, cos) = (std::f64::consts::TAU * k as f64 / sides as f64).sin_cos();
+                let offset = (u * cos + v * sin) * radius;
+                let offset = if miter > MITER_LIMIT { offset - w * (offset.dot(normal) / miter) } else { offset };
+                self.vertex(segment.start + offset, segment.color);
+            }
+            if let Some(next) = directions.get(i + 1) {
+                let carried = u - *next * u.dot(*next);
+                u = if carried.length() > 1e-9 { carried.normalize() } else { v };
+            }
+        }
+        let last = branch.last().unwrap();
+        let w = directions[directions.len() - 1];
+        let v = w.cross(u);
+        for k in 0..sides {
+            let (sin, cos) = (std::f64::consts::TAU * k as f64 / sides as f64).sin_cos();
+            self.vertex(last.end + (u * cos + v * sin) * radius, last.color);
+        }
+        let start_center = self.vertex(branch[0].start, branch[0].color);
+        let end_center = self.vertex(last.end, last.color);
+
+        let sides = sides as u32;
+        for ring in 0..branch.len() as u32 {
+            let (this, next) = (first + ring * sides, first + (ring + 1) * sides);
+            for i in 0..sides {
+                let j = (i + 1) % sides;
+                let (a, b, c, d) = (this + i, this + j, next + i, next + j);
+                self.triangles.push([a, b, d]);
+                self.triangles.push([a, d, c]);
+            }
+        }
+        let end = first + branch.len() as u32 * sides;
+        for i in 0..sides {
+            let j = (i + 1) % sides;
+            self.triangles.push([start_center, first + j, first + i]);
+            self.triangles.push([end_center, end + i, end + j]);
+        }
+    }
+
+    fn face(&mut self, polygon: &Polygon) {
+        if polygon.points.len() < 3 {
+            return;
+        }
+        let first = self.vertices.len() as u32;
+        for point in polygon.points.iter() {
+            self.vertex(*point, polygon.color);
+        }
+        for i in 1..polygon.points.len() as u32 - 1 {
+            self.triangles.push([first, first + i, first + i + 1]);
+        }
+    }
+
+    // wavefront obj with the vertex colors after the positions, the way
+    // blender and meshlab read them. y is up, so turtle z becomes y
+    pub fn obj(&self) -> String {
+        let mut result = String::new();
+        for (p, (r, g, b)) in self.vertices.iter().zip(self.colors.iter()) {
+            let (x, y, z) = y_up(*p);
+            let n = |v: f64| number(v, 6);
+            let _ = writeln!(result, "v {} {} {} {} {} {}", n(x), n(y), n(z), n(*r), n(*g), n(*b));
+        }
+        for [a, b, c] in self.triangles.iter() {
+            let _ = writeln!(result, "f {} {} {}", a + 1, b + 1, c + 1);
+        }
+        result
+    }
+
+    // binary stl, which has no colors. z is up, as it is for the turtle and
+    // for printers
+    pub fn stl(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(84 + 50 * self.triangles.len());
+        let mut header = [0u8; 80];
+        header[..9].copy_from_slice(b"l-systems");
+        result.extend_from_slice(&header);
+        result.extend_from_slice(&(self.triangles.len() as u32).to_le_bytes());
+        for [a, b, c] in self.triangles.iter() {
+            let [a, b, c] = [a, b, c].map(|i| self.vertices[*i as usize]);
+            let normal = (b - a).cross(c - a);
+            let normal = if normal.length() > 0.0 { normal.normalize() } else { normal };
+            for p in [normal, a, b, c] {
+                for v in [p.x, p.y, p.z] {
+                    result.extend_from_slice(&(v as f32).to_le_bytes());
+                }
+            }
+            result.extend_from_slice(&0u16.to_le_bytes());
+        }
+        result
+    }
+
+    // binary gltf: one mesh with vertex colors and a double sided material.
+    // y is up, so turtle z becomes y
+    pub fn glb(&self) -> Vec<u8> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let (mut min, mut max) = ([f32::MAX; 3], [f32::MIN; 3]);
+        for p in self.vertices.iter() {
+            let (x, y, z) = y_up(*p);
+            for (i, v) in [x as f32, y as f32, z as f32].into_iter().enumerate() {
+                buffer.extend_from_slice(&v.to_le_bytes());
+                min[i] = min[i].min(v);
+                max[i] = max[i].max(v);
+            }
+        }
+        let positions = buffer.len();
+        for (r, g, b) in self.colors.iter() {
+            for v in [*r, *g, *b] {
+                buffer.extend_from_slice(&(v as f32).to_le_bytes());
+            }
+        }
+        let colors = buffer.len() - positions;
+        for i in self.triangles.iter().flatten() {
+            buffer.extend_from_slice(&i.to_le_bytes());
+        }
+        let indices = buffer.len() - positions - colors;
+
+        let asset = r#""asset":{"version":"2.0","generator":"l-systems"}"#;
+        let mut json = if self.triangles.is_empty() {
+            format!(r#"{{{},"scene":0,"scenes":[{{"nodes":[]}}]}}"#, asset)
+        } else {
+            let list = |v: [f32; 3]| format!("[{},{},{}]", v[0], v[1], v[2]);
+            let count = self.vertices.len();
+            [
+                format!("{{{}", asset),
+                r#""scene":0,"scenes":[{"nodes":[0]}],"nodes":[{"mesh":0}]"#.to_string(),
+                r#""meshes":[{"primitives":[{"attributes":{"POSITION":0,"COLOR_0":1},"indices":2,"material":0}]}]"#.to_string(),
+                r#""materials":[{"doubleSided":true,"pbrMetallicRoughness":{"metallicFactor":0}}]"#.to_string(),
+                format!(r#""buffers":[{{"byteLength":{}}}]"#, buffer.len()),
+                format!(
+                    concat!(
+                        r#""bufferViews":[{{"buffer":0,"byteOffset":0,"byteLength":{},"target":34962}},"#,
+                        r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":34962}},"#,
+                        r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":34963}}]"#,
+                    ),
+                    positions, positions, colors, positions + colors, indices,
+                ),
+                format!(
+                    concat!(
+                        r#""accessors":[{{"bufferView":0,"componentType":5126,"count":{},"type":"VEC3","min":{},"max":{}}},"#,
+                        r#"{{"bufferView":1,"componentType":5126,"count":{},"type":"VEC3"}},"#,
+                        r#"{{"bufferView":2,"componentType":5125,"count":{},"type":"SCALAR"}}]}}"#,
+                    ),
+                    count, list(min), list(max), count, 3 * self.triangles.len(),
+                ),
+            ].join(",")
+        };
+        // chunks are padded to four bytes, json with spaces
+        while json.len() % 4 != 0 {
+            json.push(' ');
+        }
+
+        let mut result = Vec::new();
+        let length = 12 + 8 + json.len() + if buffer.is_empty() { 0 } else { 8 + buffer.len() };
+        result.extend_from_slice(b"glTF");
+        result.extend_from_slice(&2u32.to_le_bytes());
+        result.extend_from_slice(&(length as u32).to_le_bytes());
+        result.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        result.extend_from_slice(b"JSON");
+        result.extend_from_slice(json.as_bytes());
+        if !buffer.is_empty() {
+            result.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+            result.extend_from_slice(b"BIN\0");
+            result.extend_from_slice(&buffer);
+        }
+        result
+    }
+}
+
+// rings aren't mitered at bends sharper than this, measured as the cosine
+// between a segment and the normal of the plane halving the bend, so they
+// don't reach far out of the tube
+const MITER_LIMIT: f64 = 0.25;
+
+// the segments in runs where each carries on from the end of the one before
+// at the same width. a branch that turns back on itself is split there
+fn branches(segments: &[Segment]) -> Vec<&[Segment]> {
+    let mut result: Vec<&[Segment]> = Vec::new();
+    let mut start = 0;
+    for i in 1..=segments.len() {
+        let joined = segments.get(i).is_some_and(|segment| {
+            let before = &segments[i - 1];
+            let (a, b) = (before.end - before.start, segment.end - segment.start);
+            segment.start == before.end && segment.width == before.width && a.dot(b) > -0.999 * a.length() * b.length()
+        });
+        if !joined {
+            result.push(&segments[start..i]);
+            start = i;
+        }
+    }
+    result.retain(|branch| !branch.is_empty());
+    result
+}
+
+// turtle space has z up. obj and gltf have y up
+fn y_up(p: Vec3) -> (f64, f64, f64) {
+    (p.x, p.z, -p.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model() -> Model {
+        Model {
+            segments: vec![Segment {
+                start: Vec3::default(),
+                end: Vec3::Z * 10.0,
+                width: 2.0,
+                color: (1.0, 0.0, 0.0),
+                depth: 0,
+                command: 0,
+                symbol: None,
+            }],
+            polygons: vec![Polygon { points: vec![Vec3::default(), Vec3::X, Vec3::X + Vec3::Y, Vec3::Y], color: (0.0, 1.0, 0.0), depth: 0 }],
+        }
+    }
+
+    #[test]
+    fn tubes() {
+        let mesh = Mesh::new(&model(), &MeshOptions { sides: 6, ..MeshOptions::default() });
+        // two rings and their centers, then the square
+        assert_eq!(mesh.vertices.len(), 6 * 2 + 2 + 4);
+        assert_eq!(mesh.triangles.len(), 6 * 4 + 2);
+        for p in mesh.vertices[..12].iter() {
+            assert!(((p.x * p.x + p.y * p.y).sqrt() - 1.0).abs() < 1e-9);
+        }
+        // every triangle of the tube faces away from its axis, or along it
+        // at the caps
+        for [a, b, c] in mesh.triangles[..24].iter() {
+            let [a, b, c] = [a, b, c].map(|i| mesh.vertices[*i as usize]);
+            let normal = (b - a).cross(c - a);
+            let center = (a + b + c) * (1.0 / 3.0);
+            let outward = Vec3::new(center.x, center.y, 0.0) + Vec3::Z * (center.z - 5.0);
+            assert!(normal.dot(outward) > 0.0);
+        }
+        assert_eq!(mesh.triangles[24..], [[14, 15, 16], [14, 16, 17]]);
+        assert_eq!(mesh.colors[14], (0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn branches_share_rings() {
+        let segment = |start: Vec3, end: Vec3, width: f64| Segment {
+            start, end, width, color: (1.0, 1.0, 1.0), depth: 0, command: 0, symbol: None,
+        };
+        let top = Vec3::Z * 10.0;
+        let model = Model {
+            segments: vec![
+                segment(Vec3::default(), top, 2.0),
+                segment(top, top + Vec3::X * 10.0, 2.0),
+                segment(Vec3::default(), Vec3::X * -10.0, 2.0),
+                segment(Vec3::X * -10.0, Vec3::X * -20.0, 1.0),
+            ],
+            polygons: Vec::new(),
+        };
+        assert_eq!(branches(&model.segments).iter().map(|branch| branch.len()).collect::<Vec<_>>(), [2, 1, 1]);
+
+        let mesh = Mesh::new(&model, &MeshOptions { sides: 6, ..MeshOptions::default() });
+        // three rings on the bent branch and two on the others, each with
+        // two caps
+        assert_eq!(mesh.vertices.len(), (6 * 3 + 2) + 2 * (6 * 2 + 2));
+        assert_eq!(mesh.triangles.len(), (6 * 2 * 2 + 6 * 2) + 2 * (6 * 2 + 6 * 2));
+        // the shared ring is mitered: it lies on the plane halving the bend,
+        // as far from both segments as the tube is thick
+        let normal = (Vec3::X + Vec3::Z).normalize();
+        for p in mesh.vertices[6..12].iter() {
+            assert!((*p - top).dot(normal).abs() < 1e-9);
+            assert!(((p.x * p.x + p.y * p.y).sqrt() - 1.0).abs() < 1e-9);
+            assert!(((p.y * p.y + (p.z - 10.0) * (p.z - 10.0)).sqrt() - 1.0).abs() < 1e-9);
+        }
+        // closed and facing out, enclosing hexagonal prisms as long as the
+        // branches, with no volume lost or doubled at the bend
+        let volume: f64 = mesh.triangles.iter().map(|[a, b, c]| {
+            let [a, b, c] = [a, b, c].map(|i| mesh.vertices[*i as usize]);
+            a.dot(b.cross(c)) / 6.0
+        }).sum();
+        let hexagon = 3.0 * 3f64.sqrt() / 2.0;
+        assert!((volume - hexagon * (20.0 + 10.0 + 0.25 * 10.0)).abs() < 1e-9, "{}", volume);
+    }
+
+    #[test]
+    fn clamped_colors() {
+        let mut model = model();
+        model.segments[0].color = (300.0 / 255.0, -0.5, 0.25);
+        let mesh = Mesh::new(&model, &MeshOptions::default());
+        assert_eq!(mesh.colors[0], (1.0, 0.0, 0.25));
+        assert!(mesh.obj().contains("\nv 0 10 0 1 0 0.25\n"));
+    }
+
+    #[test]
+    fn formats() {
+        let mesh = Mesh::new(&model(), &MeshOptions::default());
+        let obj = mesh.obj();
+        assert_eq!(obj.lines().filter(|line| line.starts_with("v ")).count(), mesh.vertices.len());
+        assert!(obj.contains("\nv 0 10 0 1 0 0\n"));
+        assert!(obj.ends_with("f 19 21 22\n"));
+
+        let stl = mesh.stl();
+        assert_eq!(stl.len(), 84 + 50 * mesh.triangles.len());
+        assert_eq!(u32::from_le_bytes(stl[80..84].try_into().unwrap()), mesh.triangles.len() as u32);
+
+        let glb = mesh.glb();
+        assert_eq!(&glb[..4], b"glTF");
+        assert_eq!(u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize, glb.len());
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        assert_eq!(json_length % 4, 0);
+        let json = std::str::from_utf8(&glb[20..20 + json_length]).unwrap();
+        assert!(json.contains(r#""min":[-1,0,-1],"max":[1,10,1]"#));
+        let binary = 20 + json_length;
+        assert_eq!(&glb[binary + 4..binary + 8], b"BIN\0");
+        assert_eq!(glb.len(), binary + 8 + mesh.vertices.len() * 24 + mesh.triangles.len() * 12);
+
+        let empty = Mesh::default().glb();
+        assert_eq!(u32::from_le_bytes(empty[8..12].try_into().unwrap()) as usize, empty.len());
+    }
+}
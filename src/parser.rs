@@ -1,5 +1,6 @@
 use crate::l_system::*;
 use crate::turtle::*;
+use crate::vector::*;
 
 use pest::{iterators::Pair, Parser};
 use pest_derive::Parser;
@@ -10,10 +11,15 @@ use std::str::FromStr;
 #[grammar_inline = r##"
 WHITESPACE = _{ " " | "\t" | NEWLINE }
 
-number = { "-"? ~ ASCII_DIGIT+ ~ ("." ~ ASCII_DIGIT+)? }
-positive_integer = { ASCII_NONZERO_DIGIT ~ ASCII_DIGIT* }
+number = @{ "-"? ~ ASCII_DIGIT+ ~ ("." ~ ASCII_DIGIT+)? }
+positive_integer = @{ ASCII_NONZERO_DIGIT ~ ASCII_DIGIT* }
+integer = @{ ASCII_DIGIT+ }
 
-special_char = { "!" | "@" | "#" | "$" | "%" | "^" | "&" | "*" | "-" | "=" | "+" | "_" | "~"}
+color_hex = @{ "#" ~ ASCII_HEX_DIGIT{6} }
+color_rgb = { number ~ number ~ number }
+color = { color_hex | color_rgb }
+
+special_char = { "!" | "@" | "#" | "$" | "%" | "^" | "&" | "*" | "-" | "=" | "+" | "_" | "~" | "/" | "\\" | "|" }
 puncuation_char = { "." | ";" | ":" | "'" | "`" }
 bracket_char = { "[" | "]" | "{" | "}" | "<" | ">" }
 valid_char = { ASCII_ALPHANUMERIC | special_char | puncuation_char | bracket_char }
@@ -24,21 +30,60 @@ lsystem_rule_rhs = { valid_char+ }
 lsystem_rule = { valid_char ~ "->" ~ lsystem_rule_rhs }
 lsystem_rules = { "(" ~ lsystem_rule* ~ ("," ~ lsystem_rule)* ~ ")" }
 
-turtle_command_move = { "MOVE" ~ number }
-turtle_command_turn = { "TURN" ~ number }
+spread = { "~" ~ number }
+turtle_command_move = { "MOVE" ~ number ~ spread? }
+turtle_command_turn = { "TURN" ~ number ~ spread? }
+turtle_command_yaw = { "YAW" ~ number }
+turtle_command_pitch = { "PITCH" ~ number }
+turtle_command_roll = { "ROLL" ~ number }
+turtle_command_turn_around = { "TURNAROUND" }
 turtle_command_push = { "PUSH" }
 turtle_command_pop = { "POP" }
 turtle_command_pen_up = { "PEN" ~ "UP" }
 turtle_command_pen_down = { "PEN" ~ "DOWN" }
 turtle_command_repeat = { "REPEAT" ~ positive_integer ~ turtle_program }
+turtle_command_color_index = { "COLORINDEX" ~ integer }
+turtle_command_color = { "COLOR" ~ color }
+turtle_command_scale_width = { "WIDTH" ~ "*=" ~ number }
+turtle_command_width = { "WIDTH" ~ number }
+turtle_command_scale = { "SCALE" ~ number }
+turtle_command_polygon_begin = { "POLYGON" ~ "BEGIN" }
+turtle_command_polygon_end = { "POLYGON" ~ "END" }
+turtle_command_vertex = { "VERTEX" }
+turtle_command_arc = { "ARC" ~ number ~ number }
+turtle_command_circle = { "CIRCLE" ~ number }
+turtle_command_dot = { "DOT" ~ number }
+turtle_command_goto = { "GOTO" ~ number ~ number }
+turtle_command_heading = { "HEADING" ~ number }
+turtle_command_home = { "HOME" }
+turtle_command_face = { "FACE" ~ number ~ number }
 
 turtle_command = { turtle_command_move
+                 | turtle_command_turn_around
                  | turtle_command_turn
+                 | turtle_command_yaw
+                 | turtle_command_pitch
+                 | turtle_command_roll
                  | turtle_command_push
                  | turtle_command_pop
                  | turtle_command_pen_up
                  | turtle_command_pen_down
                  | turtle_command_repeat
+                 | turtle_command_color_index
+                 | turtle_command_color
+                 | turtle_command_scale_width
+                 | turtle_command_width
+                 | turtle_command_scale
+                 | turtle_command_polygon_begin
+                 | turtle_command_polygon_end
+                 | turtle_command_vertex
+                 | turtle_command_arc
+                 | turtle_command_circle
+                 | turtle_command_dot
+                 | turtle_command_goto
+                 | turtle_command_heading
+                 | turtle_command_home
+                 | turtle_command_face
                  }
 turtle_commands = { turtle_command ~ ("," ~ turtle_command)* }
 turtle_program = { "(" ~ turtle_commands? ~ ")" }
@@ -47,22 +92,31 @@ lsystem_interpreter_rule = { valid_char ~ "->" ~ turtle_program }
 lsystem_interpreter_rules = { lsystem_interpreter_rule ~ ("," ~ lsystem_interpreter_rule)* }
 lsystem_interpreter = { "(" ~ lsystem_interpreter_rules? ~ ")" }
 
+lsystem_palette = { "PALETTE" ~ "(" ~ color ~ ("," ~ color)* ~ ")" }
+lsystem_tropism = { "TROPISM" ~ "(" ~ number ~ "," ~ number ~ ("," ~ number)? ~ ")" ~ number }
+jitter_angle = { "angle" ~ "=" ~ number ~ "%" }
+jitter_length = { "length" ~ "=" ~ number ~ "%" }
+jitter_seed = { "seed" ~ "=" ~ integer }
+lsystem_jitter = { "JITTER" ~ (jitter_angle | jitter_length | jitter_seed)+ }
+lsystem_declaration = { lsystem_palette | lsystem_tropism | lsystem_jitter }
+
 lsystem = { SOI ~ "LSYSTEM" ~ "("
           ~ lsystem_start_value ~ ","
           ~ lsystem_rules ~ ","
           ~ lsystem_interpreter
+          ~ ("," ~ lsystem_declaration)*
           ~ ")" ~ EOI
           }
 "##]
 pub struct LSystemParser;
 
-fn to_start_value(pair: Pair<Rule>) -> &str {
+fn to_start_value(pair: Pair<'_, Rule>) -> &str {
     pair.as_str()
 }
 
 fn to_rule(pair: Pair<Rule>) -> (char, String) {
     let mut items = pair.into_inner();
-    let k = items.next().unwrap().as_str().chars().nth(0).unwrap();
+    let k = items.next().unwrap().as_str().chars().next().unwrap();
     let v = items.next().unwrap().as_str().to_owned();
     (k, v)
 }
@@ -80,70 +134,221 @@ fn to_f64(pair: Pair<Rule>) -> f64 {
     f64::from_str(pair.as_str()).expect("failed to parse f64")
 }
 
-fn to_positive_integer(pair: Pair<Rule>) -> u32 {
-    u32::from_str(pair.as_str().trim()).expect("failed to parse u32")
+// an error at `pair`, for input the grammar accepts but can't be used.
+// worded like the errors from the grammar
+fn invalid(pair: &Pair<Rule>, message: &str) -> String {
+    format!("parse error at: Pos({:?}): {}", pair.as_span().start_pos().line_col(), message)
 }
 
-fn to_turtle_command(pair: Pair<Rule>) -> TurtleCommand {
+fn to_positive_integer(pair: Pair<Rule>) -> Result<u32, String> {
+    u32::from_str(pair.as_str().trim()).map_err(|_| invalid(&pair, "integer too large"))
+}
+
+// `#rrggbb`, or red, green and blue components between 0 and 255
+fn to_color(pair: Pair<Rule>) -> Color {
     let item = pair.into_inner().next().unwrap();
     match item.as_rule() {
+        Rule::color_hex => {
+            let hex = &item.as_str()[1..];
+            let component = |i: usize| {
+                u8::from_str_radix(&hex[i..i + 2], 16).expect("failed to parse hex color") as f64 / 255.0
+            };
+            (component(0), component(2), component(4))
+        }
+        Rule::color_rgb => {
+            let mut items = item.into_inner();
+            let mut component = || to_f64(items.next().unwrap()) / 255.0;
+            (component(), component(), component())
+        }
+        _ => panic!("failed to match color rule")
+    }
+}
+
+// `(x, y) e` or `(x, y, z) e`
+fn to_tropism(pair: Pair<Rule>) -> Tropism {
+    let mut numbers: Vec<f64> = pair.into_inner().map(to_f64).collect();
+    let susceptibility = numbers.pop().unwrap();
+    numbers.resize(3, 0.0);
+    Tropism {
+        direction: Vec3::new(numbers[0], numbers[1], numbers[2]),
+        susceptibility,
+    }
+}
+
+fn to_spread(pair: Pair<Rule>) -> f64 {
+    to_f64(pair.into_inner().next().unwrap()).abs()
+}
+
+// percentages become fractions. anything left out is 0
+fn to_jitter(pair: Pair<Rule>) -> Result<Jitter, String> {
+    let mut jitter = Jitter::default();
+    for item in pair.into_inner() {
+        let rule = item.as_rule();
+        let value = item.into_inner().next().unwrap();
+        match rule {
+            Rule::jitter_angle => jitter.angle = to_f64(value) / 100.0,
+            Rule::jitter_length => jitter.length = to_f64(value) / 100.0,
+            Rule::jitter_seed => {
+                jitter.seed = u64::from_str(value.as_str()).map_err(|_| invalid(&value, "integer too large"))?;
+            }
+            _ => panic!("failed to match jitter rule")
+        }
+    }
+    Ok(jitter)
+}
+
+fn to_turtle_command(pair: Pair<Rule>) -> Result<TurtleCommand, String> {
+    let item = pair.into_inner().next().unwrap();
+    let command = match item.as_rule() {
         Rule::turtle_command_move => {
-            let v = to_f64(item.into_inner().next().unwrap());
-            TurtleCommand::Move(v)
+            let mut item = item.into_inner();
+            let v = to_f64(item.next().unwrap());
+            match item.next() {
+                Some(spread) => TurtleCommand::RandomMove(v, to_spread(spread)),
+                None => TurtleCommand::Move(v),
+            }
         }
         Rule::turtle_command_turn => {
+            let mut item = item.into_inner();
+            let v = to_f64(item.next().unwrap());
+            match item.next() {
+                Some(spread) => TurtleCommand::RandomTurn(v, to_spread(spread)),
+                None => TurtleCommand::Turn(v),
+            }
+        }
+        Rule::turtle_command_yaw => {
+            let v = to_f64(item.into_inner().next().unwrap());
+            TurtleCommand::Yaw(v)
+        }
+        Rule::turtle_command_pitch => {
             let v = to_f64(item.into_inner().next().unwrap());
-            TurtleCommand::Turn(v)
+            TurtleCommand::Pitch(v)
         }
+        Rule::turtle_command_roll => {
+            let v = to_f64(item.into_inner().next().unwrap());
+            TurtleCommand::Roll(v)
+        }
+        Rule::turtle_command_turn_around => TurtleCommand::TurnAround,
         Rule::turtle_command_push => TurtleCommand::Push,
         Rule::turtle_command_pop => TurtleCommand::Pop,
         Rule::turtle_command_pen_up => TurtleCommand::PenUp,
         Rule::turtle_command_pen_down => TurtleCommand::PenDown,
         Rule::turtle_command_repeat => {
             let mut item = item.into_inner();
-            let n = to_positive_integer(item.next().unwrap());
-            let cs = to_turtle_program(item.next().unwrap());
+            let n = to_positive_integer(item.next().unwrap())?;
+            let cs = to_turtle_program(item.next().unwrap())?;
             TurtleCommand::Repeat(n, cs)
         }
+        Rule::turtle_command_color_index => {
+            let i = to_positive_integer(item.into_inner().next().unwrap())?;
+            TurtleCommand::ColorIndex(i)
+        }
+        Rule::turtle_command_color => {
+            let (r, g, b) = to_color(item.into_inner().next().unwrap());
+            TurtleCommand::Color(r, g, b)
+        }
+        Rule::turtle_command_scale_width => {
+            let v = to_f64(item.into_inner().next().unwrap());
+            TurtleCommand::ScaleWidth(v)
+        }
+        Rule::turtle_command_width => {
+            let v = to_f64(item.into_inner().next().unwrap());
+            TurtleCommand::Width(v)
+        }
+        Rule::turtle_command_scale => {
+            let v = to_f64(item.into_inner().next().unwrap());
+            TurtleCommand::Scale(v)
+        }
+        Rule::turtle_command_polygon_begin => TurtleCommand::PolygonBegin,
+        Rule::turtle_command_polygon_end => TurtleCommand::PolygonEnd,
+        Rule::turtle_command_vertex => TurtleCommand::Vertex,
+        Rule::turtle_command_arc => {
+            let mut item = item.into_inner();
+            let radius = to_f64(item.next().unwrap());
+            let angle = to_f64(item.next().unwrap());
+            TurtleCommand::Arc(radius, angle)
+        }
+        Rule::turtle_command_circle => {
+            let v = to_f64(item.into_inner().next().unwrap());
+            TurtleCommand::Circle(v)
+        }
+        Rule::turtle_command_dot => {
+            let v = to_f64(item.into_inner().next().unwrap());
+            TurtleCommand::Dot(v)
+        }
+        Rule::turtle_command_goto => {
+            let mut item = item.into_inner();
+            let x = to_f64(item.next().unwrap());
+            let y = to_f64(item.next().unwrap());
+            TurtleCommand::Goto(x, y)
+        }
+        Rule::turtle_command_heading => {
+            let v = to_f64(item.into_inner().next().unwrap());
+            TurtleCommand::Heading(v)
+        }
+        Rule::turtle_command_home => TurtleCommand::Home,
+        Rule::turtle_command_face => {
+            let mut item = item.into_inner();
+            let x = to_f64(item.next().unwrap());
+            let y = to_f64(item.next().unwrap());
+            TurtleCommand::Face(x, y)
+        }
         _ => panic!("failed to match turtle command rule")
-    }
+    };
+    Ok(command)
 }
 
-fn to_turtle_program(pair: Pair<Rule>) -> Vec<TurtleCommand> {
+fn to_turtle_program(pair: Pair<Rule>) -> Result<Vec<TurtleCommand>, String> {
     let mut result = Vec::new();
     for item in pair.into_inner().next().unwrap().into_inner() {
-        result.push(to_turtle_command(item));
+        result.push(to_turtle_command(item)?);
     }
-    result
+    Ok(result)
 }
 
-fn to_interpreter_rule(pair: Pair<Rule>) -> (char, Vec<TurtleCommand>) {
+fn to_interpreter_rule(pair: Pair<Rule>) -> Result<(char, Vec<TurtleCommand>), String> {
     let mut items = pair.into_inner();
-    let k = items.next().unwrap().as_str().chars().nth(0).unwrap();
-    let v = to_turtle_program(items.next().unwrap());
-    (k, v)
+    let k = items.next().unwrap().as_str().chars().next().unwrap();
+    let v = to_turtle_program(items.next().unwrap())?;
+    Ok((k, v))
 }
 
-fn to_interpreter(pair: Pair<Rule>) -> HashMap<char, Vec<TurtleCommand>> {
+fn to_interpreter(pair: Pair<Rule>) -> Result<HashMap<char, Vec<TurtleCommand>>, String> {
     let mut result = HashMap::new();
     for item in pair.into_inner().next().unwrap().into_inner() {
-        let (k, v) = to_interpreter_rule(item);
+        let (k, v) = to_interpreter_rule(item)?;
         result.insert(k, v);
     }
-    result
+    Ok(result)
 }
 
 pub fn parse(input: &str) -> Result<LSystem, String> {
     match LSystemParser::parse(Rule::lsystem, input) {
         Ok(mut result) => {
             let mut pair = result.next().unwrap().into_inner();
-            Ok(
-                LSystem::new(
-                    to_start_value(pair.next().unwrap()),
-                    to_rules(pair.next().unwrap()),
-                    to_interpreter(pair.next().unwrap())
-                )
-            )
+            let start = to_start_value(pair.next().unwrap());
+            let rules = to_rules(pair.next().unwrap());
+            let interpreter = to_interpreter(pair.next().unwrap())?;
+            let mut lsystem = LSystem::new(start, rules, interpreter);
+            for item in pair {
+                if item.as_rule() != Rule::lsystem_declaration {
+                    continue;
+                }
+                let declaration = item.into_inner().next().unwrap();
+                match declaration.as_rule() {
+                    Rule::lsystem_palette => {
+                        lsystem = lsystem.with_palette(declaration.into_inner().map(to_color).collect());
+                    }
+                    Rule::lsystem_tropism => {
+                        lsystem = lsystem.with_tropism(to_tropism(declaration));
+                    }
+                    Rule::lsystem_jitter => {
+                        lsystem = lsystem.with_jitter(to_jitter(declaration)?);
+                    }
+                    _ => panic!("failed to match declaration rule")
+                }
+            }
+            Ok(lsystem)
         }
         Err(err) => {
             Err(format!("parse error at: {:?}", err.line_col))
@@ -178,5 +383,170 @@ mod tests {
     generate_test!(plant, PLANT.1, PLANT.2);
     generate_test!(levy, LEVY.1, LEVY.2);
     generate_test!(grapes, GRAPES.1, GRAPES.2);
-}
+    generate_test!(bush, BUSH.1, BUSH.2);
+    generate_test!(leaves, LEAVES.1, LEAVES.2);
 
+    #[test]
+    fn orientation_commands() {
+        let actual = parse(r#"LSYSTEM (
+            A,
+            (A -> A),
+            (A -> (YAW 10, PITCH -20, ROLL 30.5, TURNAROUND))
+        )"#);
+        let expected = LSystem::new(
+            "A",
+            HashMap::from([('A', "A".into())]),
+            HashMap::from([('A', vec![
+                TurtleCommand::Yaw(10.0),
+                TurtleCommand::Pitch(-20.0),
+                TurtleCommand::Roll(30.5),
+                TurtleCommand::TurnAround,
+            ])]),
+        );
+        assert_eq!(actual, Ok(expected));
+    }
+
+    #[test]
+    fn colors() {
+        let actual = parse(r#"LSYSTEM (
+            A,
+            (A -> A),
+            (A -> (COLOR 255 0 51, COLOR #00ff80, COLORINDEX 1)),
+            PALETTE (#ff0000, 0 0 255)
+        )"#);
+        let expected = LSystem::new(
+            "A",
+            HashMap::from([('A', "A".into())]),
+            HashMap::from([('A', vec![
+                TurtleCommand::Color(1.0, 0.0, 0.2),
+                TurtleCommand::Color(0.0, 1.0, 128.0 / 255.0),
+                TurtleCommand::ColorIndex(1),
+            ])]),
+        ).with_palette(vec![(1.0, 0.0, 0.0), (0.0, 0.0, 1.0)]);
+        assert_eq!(actual, Ok(expected));
+    }
+
+    #[test]
+    fn integers_too_large() {
+        let program = |commands: &str| parse(&format!("LSYSTEM (A, (A -> A), (A -> ({})))", commands));
+        assert_eq!(program("COLORINDEX 99999999999"), Err("parse error at: Pos((1, 41)): integer too large".to_string()));
+        assert!(program("REPEAT 99999999999 (PUSH)").is_err());
+        assert!(program("COLORINDEX 4294967295").is_ok());
+    }
+
+    #[test]
+    fn width_and_scale() {
+        let actual = parse(r#"LSYSTEM (
+            A,
+            (A -> A),
+            (A -> (WIDTH 3, WIDTH *= 0.7, SCALE 0.9))
+        )"#);
+        let expected = LSystem::new(
+            "A",
+            HashMap::from([('A', "A".into())]),
+            HashMap::from([('A', vec![
+                TurtleCommand::Width(3.0),
+                TurtleCommand::ScaleWidth(0.7),
+                TurtleCommand::Scale(0.9),
+            ])]),
+        );
+        assert_eq!(actual, Ok(expected));
+    }
+
+    #[test]
+    fn curves() {
+        let actual = parse(r#"LSYSTEM (
+            A,
+            (A -> A),
+            (A -> (ARC 10 -90, CIRCLE 5, DOT 1.5))
+        )"#);
+        let expected = LSystem::new(
+            "A",
+            HashMap::from([('A', "A".into())]),
+            HashMap::from([('A', vec![
+                TurtleCommand::Arc(10.0, -90.0),
+                TurtleCommand::Circle(5.0),
+                TurtleCommand::Dot(1.5),
+            ])]),
+        );
+        assert_eq!(actual, Ok(expected));
+    }
+
+    #[test]
+    fn absolute_positioning() {
+        let actual = parse(r#"LSYSTEM (
+            A,
+            (A -> A),
+            (A -> (GOTO -10 20.5, HEADING 90, HOME, FACE 3 4))
+        )"#);
+        let expected = LSystem::new(
+            "A",
+            HashMap::from([('A', "A".into())]),
+            HashMap::from([('A', vec![
+                TurtleCommand::Goto(-10.0, 20.5),
+                TurtleCommand::Heading(90.0),
+                TurtleCommand::Home,
+                TurtleCommand::Face(3.0, 4.0),
+            ])]),
+        );
+        assert_eq!(actual, Ok(expected));
+    }
+
+    #[test]
+    fn tropism() {
+        let source = |declaration: &str| format!("LSYSTEM (A, (A -> A), (A -> (MOVE 1)), {})", declaration);
+        let lsystem = || LSystem::new(
+            "A",
+            HashMap::from([('A', "A".into())]),
+            HashMap::from([('A', vec![TurtleCommand::Move(1.0)])]),
+        );
+        assert_eq!(
+            parse(&source("TROPISM (0, -1) 0.2")),
+            Ok(lsystem().with_tropism(Tropism { direction: Vec3::new(0.0, -1.0, 0.0), susceptibility: 0.2 })),
+        );
+        assert_eq!(
+            parse(&source("PALETTE (#ffffff), TROPISM (0.5, 0, -1) 0.1")),
+            Ok(lsystem()
+                .with_palette(vec![(1.0, 1.0, 1.0)])
+                .with_tropism(Tropism { direction: Vec3::new(0.5, 0.0, -1.0), susceptibility: 0.1 })),
+        );
+    }
+
+    #[test]
+    fn jitter() {
+        let actual = parse(r#"LSYSTEM (
+            A,
+            (A -> A),
+            (A -> (TURN 25 ~5, MOVE 10 ~ 1.5, TURN -25)),
+            JITTER angle=5% length=10% seed=42
+        )"#);
+        let expected = LSystem::new(
+            "A",
+            HashMap::from([('A', "A".into())]),
+            HashMap::from([('A', vec![
+                TurtleCommand::RandomTurn(25.0, 5.0),
+                TurtleCommand::RandomMove(10.0, 1.5),
+                TurtleCommand::Turn(-25.0),
+            ])]),
+        ).with_jitter(Jitter { angle: 0.05, length: 0.1, seed: 42 });
+        assert_eq!(actual, Ok(expected));
+
+        let actual = parse("LSYSTEM (A, (A -> A), (A -> (MOVE 1)), JITTER length=20%)");
+        let expected = LSystem::new(
+            "A",
+            HashMap::from([('A', "A".into())]),
+            HashMap::from([('A', vec![TurtleCommand::Move(1.0)])]),
+        ).with_jitter(Jitter { angle: 0.0, length: 0.2, seed: 0 });
+        assert_eq!(actual, Ok(expected));
+
+        let actual = parse("LSYSTEM (A, (A -> A), (A -> (MOVE 1)), JITTER seed=99999999999999999999999)");
+        assert_eq!(actual, Err("parse error at: Pos((1, 52)): integer too large".to_string()));
+        let actual = parse("LSYSTEM (A, (A -> A), (A -> (MOVE 1)), JITTER seed=18446744073709551615)");
+        let expected = LSystem::new(
+            "A",
+            HashMap::from([('A', "A".into())]),
+            HashMap::from([('A', vec![TurtleCommand::Move(1.0)])]),
+        ).with_jitter(Jitter { angle: 0.0, length: 0.0, seed: u64::MAX });
+        assert_eq!(actual, Ok(expected));
+    }
+}
@@ -0,0 +1,271 @@
+use std::fmt::Write;
+
+use crate::draw::*;
+use crate::render::*;
+
+// points in an inch, and in a pixel of the canvas
+const POINTS_PER_INCH: f64 = 72.0;
+const POINTS_PER_PIXEL: f64 = POINTS_PER_INCH / 96.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PageSize {
+    A4,
+    Letter,
+    // width and height of the page in points, standing upright
+    Custom(f64, f64),
+}
+
+impl PageSize {
+    // in points, standing upright
+    fn size(&self) -> (f64, f64) {
+        match self {
+            PageSize::A4 => (210.0 / 25.4 * POINTS_PER_INCH, 297.0 / 25.4 * POINTS_PER_INCH),
+            PageSize::Letter => (8.5 * POINTS_PER_INCH, 11.0 * POINTS_PER_INCH),
+            PageSize::Custom(width, height) => (*width, *height),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+#[derive(Clone, Debug)]
+pub struct PageOptions {
+    // the region printed. defaults to the bounding box of the drawing
+    pub viewport: Option<Viewport>,
+    pub size: PageSize,
+    pub orientation: Orientation,
+    // in points, on every side. the region is scaled to fit inside the
+    // margins without distortion, and centered
+    pub margin: f64,
+    // painted over the whole page behind the drawing, if any
+    pub background: Option<String>,
+}
+
+impl Default for PageOptions {
+    fn default() -> Self {
+        Self {
+            viewport: None,
+            size: PageSize::A4,
+            orientation: Orientation::Portrait,
+            margin: 36.0,
+            background: None,
+        }
+    }
+}
+
+impl PageOptions {
+    // width and height of the page in points
+    pub fn page(&self) -> (f64, f64) {
+        let (width, height) = self.size.size();
+        match self.orientation {
+            Orientation::Portrait => (width.min(height), width.max(height)),
+            Orientation::Landscape => (width.max(height), width.min(height)),
+        }
+    }
+}
+
+// the operators that differ between pdf and postscript
+struct Operators {
+    move_to: &'static str,
+    line_to: &'static str,
+    close: &'static str,
+    stroke: &'static str,
+    fill: &'static str,
+    width: &'static str,
+    stroke_color: &'static str,
+    fill_color: &'static str,
+}
+
+const PDF: Operators = Operators {
+    move_to: "m", line_to: "l", close: "h", stroke: "S", fill: "f",
+    width: "w", stroke_color: "RG", fill_color: "rg",
+};
+
+const POSTSCRIPT: Operators = Operators {
+    move_to: "moveto", line_to: "lineto", close: "closepath", stroke: "stroke", fill: "fill",
+    width: "setlinewidth", stroke_color: "setrgbcolor", fill_color: "setrgbcolor",
+};
+
+// an rgb color between 0 and 1. colors that can't be read are black
+fn rgb(style: &str) -> String {
+    let [r, g, b] = parse_color(style).unwrap_or([0, 0, 0]);
+    [r, g, b].iter().map(|c| number(*c as f64 / 255.0, 3)).collect::<Vec<_>>().join(" ")
+}
+
+// paints the drawing on the page. line widths are kept at the size they
+// are drawn on the canvas, whatever the scale of the drawing
+fn page_content(geometry: &Geometry, options: &PageOptions, operators: &Operators) -> String {
+    let (page_width, page_height) = options.page();
+    let margin = options.margin.min(page_width / 2.0).min(page_height / 2.0);
+    let placement = Placement::new(geometry, options.viewport, page_width - 2.0 * margin, page_height - 2.0 * margin);
+    let point = |p: Point| {
+        let (x, y) = placement.place(p);
+        format!("{} {}", number(x + margin, 2), number(y + margin, 2))
+    };
+
+    let mut result = String::new();
+    if let Some(background) = &options.background {
+        let _ = writeln!(result, "{} {}", rgb(background), operators.fill_color);
+        let _ = writeln!(result, "0 0 {}", operators.move_to);
+        let _ = writeln!(result, "{} 0 {}", number(page_width, 2), operators.line_to);
+        let _ = writeln!(result, "{} {} {}", number(page_width, 2), number(page_height, 2), operators.line_to);
+        let _ = writeln!(result, "0 {} {}", number(page_height, 2), operators.line_to);
+        let _ = writeln!(result, "{} {}", operators.close, operators.fill);
+    }
+    // the current stroke color, fill color and width, to leave out the ones
+    // that don't change
+    let mut stroke_color = None;
+    let mut fill_color = None;
+    let mut width = None;
+    let path = |result: &mut String, points: &[Point], closed: bool| {
+        for (i, p) in points.iter().enumerate() {
+            let _ = writeln!(result, "{} {}", point(*p), if i == 0 { operators.move_to } else { operators.line_to });
+        }
+        if closed {
+            let _ = writeln!(result, "{}", operators.close);
+        }
+    };
+    for shape in geometry.shapes.iter() {
+        match shape {
+            Shape::Polyline(polyline) => {
+                // postscript has one current color for strokes and fills
+                let color = rgb(&polyline.color);
+                if stroke_color.as_ref() != Some(&color) {
+                    let _ = writeln!(result, "{} {}", color, operators.stroke_color);
+                    if operators.stroke_color == operators.fill_color {
+                        fill_color = Some(color.clone());
+                    }
+                    stroke_color = Some(color);
+                }
+                if width != Some(polyline.width) {
+                    let _ = writeln!(result, "{} {}", number(polyline.width * POINTS_PER_PIXEL, 3), operators.width);
+                    width = Some(polyline.width);
+                }
+                path(&mut result, &polyline.points, polyline.closed);
+                let _ = writeln!(result, "{}", operators.stroke);
+            }
+            Shape::Fill(fill) => {
+                let color = rgb(&fill.color);
+                if fill_color.as_ref() != Some(&color) {
+                    let _ = writeln!(result, "{} {}", color, operators.fill_color);
+                    if operators.stroke_color == operators.fill_color {
+                        stroke_color = Some(color.clone());
+                    }
+                    fill_color = Some(color);
+                }
+                for ring in fill.rings.iter() {
+                    path(&mut result, ring, true);
+                }
+                let _ = writeln!(result, "{}", operators.fill);
+            }
+        }
+    }
+    result
+}
+
+// a single page pdf document
+pub fn pdf(geometry: &Geometry, options: &PageOptions) -> Vec<u8> {
+    let (width, height) = options.page();
+    let content = page_content(geometry, options, &PDF);
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << >> /Contents 4 0 R >>",
+            number(width, 2), number(height, 2),
+        ),
+        format!("<< /Length {} >>\nstream\n{}endstream", content.len(), content),
+    ];
+
+    let mut result = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(result.len());
+        let _ = write!(result, "{} 0 obj\n{}\nendobj\n", i + 1, object);
+    }
+    let xref = result.len();
+    let _ = write!(result, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(result, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(result, "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref);
+    result.into_bytes()
+}
+
+// an encapsulated postscript document the size of the page
+pub fn eps(geometry: &Geometry, options: &PageOptions) -> String {
+    let (width, height) = options.page();
+    let mut result = String::new();
+    let _ = writeln!(result, "%!PS-Adobe-3.0 EPSF-3.0");
+    let _ = writeln!(result, "%%BoundingBox: 0 0 {} {}", width.ceil(), height.ceil());
+    let _ = writeln!(result, "%%HiResBoundingBox: 0 0 {} {}", number(width, 2), number(height, 2));
+    let _ = writeln!(result, "%%EndComments");
+    result.push_str(&page_content(geometry, options, &POSTSCRIPT));
+    let _ = writeln!(result, "showpage");
+    let _ = writeln!(result, "%%EOF");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::tests::drawing;
+
+    #[test]
+    fn pages() {
+        let options = PageOptions { orientation: Orientation::Landscape, ..PageOptions::default() };
+        let (width, height) = options.page();
+        assert_eq!((width.round(), height.round()), (842.0, 595.0));
+        let options = PageOptions { size: PageSize::Custom(400.0, 200.0), ..PageOptions::default() };
+        assert_eq!(options.page(), (200.0, 400.0));
+    }
+
+    #[test]
+    fn content() {
+        // 10 by 5 fit into the 200 by 100 inside the margins
+        let options = PageOptions { size: PageSize::Custom(300.0, 200.0), orientation: Orientation::Landscape, margin: 50.0, ..PageOptions::default() };
+        assert_eq!(page_content(&drawing(), &options, &PDF), [
+            "1 0 0 RG",
+            "3 w",
+            "50 50 m",
+            "250 150 l",
+            "S",
+            "0 0 1 rg",
+            "50 50 m",
+            "250 50 l",
+            "250 150 l",
+            "h",
+            "f",
+            "0 1 0 RG",
+            "50 50 m",
+            "50 150 l",
+            "S",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn documents() {
+        let options = PageOptions { size: PageSize::Letter, ..PageOptions::default() };
+        let pdf = String::from_utf8(pdf(&drawing(), &options)).unwrap();
+        assert!(pdf.starts_with("%PDF-1.4\n"));
+        assert!(pdf.contains("/MediaBox [0 0 612 792]"));
+        // the cross reference table points at each object
+        let xref = pdf.find("xref\n").unwrap();
+        let startxref: usize = pdf.lines().rev().nth(1).unwrap().parse().unwrap();
+        assert_eq!(startxref, xref);
+        for (i, line) in pdf[xref..].lines().skip(3).take(4).enumerate() {
+            let offset: usize = line[..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(&format!("{} 0 obj", i + 1)));
+        }
+
+        let eps = eps(&drawing(), &options);
+        assert!(eps.contains("%%BoundingBox: 0 0 612 792\n"));
+        assert!(eps.contains("1 0 0 setrgbcolor\n3 setlinewidth\n"));
+        assert!(eps.ends_with("stroke\nshowpage\n%%EOF\n"));
+    }
+}
@@ -0,0 +1,286 @@
+use crate::draw::*;
+use crate::render::*;
+
+// rows of a pixel are sampled this many times. coverage along a row is exact
+const SAMPLES: usize = 4;
+
+fn signed_area(ring: &[Point]) -> f64 {
+    let mut area = 0.0;
+    for (i, (x0, y0)) in ring.iter().enumerate() {
+        let (x1, y1) = ring[(i + 1) % ring.len()];
+        area += x0 * y1 - x1 * y0;
+    }
+    area / 2.0
+}
+
+// the outline of a stroke as rings that all wind the same way, so they can
+// be filled together by the nonzero rule without double painting the
+// overlaps. joins are rounded, which covers the gaps between segments
+fn stroke_rings(points: &[Point], closed: bool, width: f64) -> Vec<Vec<Point>> {
+    let h = width / 2.0;
+    let mut rings = Vec::new();
+    let mut segments: Vec<(Point, Point)> = points.windows(2).map(|w| (w[0], w[1])).collect();
+    if closed && points.len() > 2 {
+        segments.push((points[points.len() - 1], points[0]));
+    }
+    for ((x0, y0), (x1, y1)) in segments {
+        let length = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+        if length == 0.0 {
+            continue;
+        }
+        let (nx, ny) = (-(y1 - y0) / length * h, (x1 - x0) / length * h);
+        rings.push(vec![(x0 + nx, y0 + ny), (x1 + nx, y1 + ny), (x1 - nx, y1 - ny), (x0 - nx, y0 - ny)]);
+    }
+    if h > 0.5 {
+        let joins = if closed { points } else { &points[1..points.len().saturating_sub(1)] };
+        let sides = (h * 2.0).ceil().clamp(8.0, 64.0) as usize;
+        for (x, y) in joins.iter() {
+            rings.push((0..sides).map(|i| {
+                let angle = std::f64::consts::TAU * i as f64 / sides as f64;
+                (x + h * angle.cos(), y + h * angle.sin())
+            }).collect());
+        }
+    }
+    for ring in rings.iter_mut() {
+        if signed_area(ring) < 0.0 {
+            ring.reverse();
+        }
+    }
+    rings
+}
+
+// an anti-aliased software rasterizer. it paints what a canvas of `width`
+// by `height` pixels showing `viewport` would, over the canvas background
+pub struct Raster {
+    pub width: u32,
+    pub height: u32,
+    // rgba, row by row from the top
+    pub pixels: Vec<u8>,
+    viewport: Viewport,
+    geometry: Geometry,
+    // shapes of `geometry` already painted
+    painted: usize,
+}
+
+impl Raster {
+    pub fn new(width: u32, height: u32, viewport: Viewport) -> Self {
+        let [r, g, b] = parse_color(BACKGROUND).unwrap();
+        let pixels = [r, g, b, 255].repeat(width as usize * height as usize);
+        Self { width, height, pixels, viewport, geometry: Geometry::new(), painted: 0 }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = 4 * (y as usize * self.width as usize + x as usize);
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]]
+    }
+
+    fn to_pixels(&self, (x, y): Point) -> Point {
+        let Viewport { x0, x1, y0, y1 } = self.viewport;
+        ((x - x0) * self.width as f64 / (x1 - x0), (y1 - y) * self.height as f64 / (y1 - y0))
+    }
+
+    fn paint(&mut self, shape: &Shape) {
+        match shape {
+            Shape::Polyline(polyline) => {
+                let points: Vec<Point> = polyline.points.iter().map(|p| self.to_pixels(*p)).collect();
+                let rings = stroke_rings(&points, polyline.closed, polyline.width);
+                self.fill(&rings, &polyline.color);
+            }
+            Shape::Fill(fill) => {
+                let rings: Vec<Vec<Point>> = fill.rings.iter()
+                    .map(|ring| ring.iter().map(|p| self.to_pixels(*p)).collect())
+                    .collect();
+                self.fill(&rings, &fill.color);
+            }
+        }
+    }
+
+    // fills the rings by the nonzero rule
+    fn fill(&mut self, rings: &[Vec<Point>], style: &str) {
+        let Some(color) = parse_color(style) else { return };
+        let (width, height) = (self.width as usize, self.height as usize);
+
+        // (top, bottom, x at top, dx/dy, winding)
+        let mut edges: Vec<(f64, f64, f64, f64, i32)> = Vec::new();
+        for ring in rings.iter() {
+            for (i, &(x0, y0)) in ring.iter().enumerate() {
+                let (x1, y1) = ring[(i + 1) % ring.len()];
+                if y0 == y1 || !(x0.is_finite() && y0.is_finite() && x1.is_finite() && y1.is_finite()) {
+                    continue;
+                }
+                let slope = (x1 - x0) / (y1 - y0);
+                if y0 < y1 {
+                    edges.push((y0, y1, x0, slope, 1));
+                } else {
+                    edges.push((y1, y0, x1, slope, -1));
+                }
+            }
+        }
+        if edges.is_empty() {
+            return;
+        }
+        edges.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let top = edges[0].0.max(0.0).floor() as usize;
+        let bottom = edges.iter().map(|e| e.1).fold(f64::MIN, f64::max).min(height as f64).ceil() as usize;
+
+        let mut coverage = vec![0.0f64; width];
+        let mut next = 0;
+        let mut active: Vec<(f64, f64, f64, f64, i32)> = Vec::new();
+        let mut crossings: Vec<(f64, i32)> = Vec::new();
+        for row in top..bottom {
+            coverage.iter_mut().for_each(|c| *c = 0.0);
+            for sample in 0..SAMPLES {
+                let y = row as f64 + (sample as f64 + 0.5) / SAMPLES as f64;
+                while next < edges.len() && edges[next].0 <= y {
+                    active.push(edges[next]);
+                    next += 1;
+                }
+                active.retain(|e| e.1 > y);
+                crossings.clear();
+                crossings.extend(active.iter().filter(|e| e.0 <= y).map(|e| (e.2 + (y - e.0) * e.3, e.4)));
+                crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+                let mut winding = 0;
+                let mut start = 0.0;
+                for &(x, w) in crossings.iter() {
+                    if winding == 0 {
+                        start = x;
+                    }
+                    winding += w;
+                    if winding == 0 {
+                        add_span(&mut coverage, start, x, 1.0 / SAMPLES as f64);
+                    }
+                }
+            }
+            for (column, c) in coverage.iter().enumerate() {
+                if *c > 0.0 {
+                    let alpha = c.min(1.0);
+                    let i = 4 * (row * width + column);
+                    for (pixel, c) in self.pixels[i..i + 3].iter_mut().zip(color) {
+                        *pixel = (*pixel as f64 * (1.0 - alpha) + c as f64 * alpha).round() as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "png")]
+    pub fn write_png(&self, writer: impl std::io::Write) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)
+    }
+
+    #[cfg(feature = "png")]
+    pub fn to_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut result = Vec::new();
+        self.write_png(&mut result)?;
+        Ok(result)
+    }
+}
+
+// adds `weight` times the part of each pixel covered by the span from `x0`
+// to `x1`
+fn add_span(coverage: &mut [f64], x0: f64, x1: f64, weight: f64) {
+    let width = coverage.len() as f64;
+    let (x0, x1) = (x0.clamp(0.0, width), x1.clamp(0.0, width));
+    if x1 <= x0 {
+        return;
+    }
+    let (first, last) = (x0.floor() as usize, x1.floor() as usize);
+    if first == last {
+        coverage[first] += (x1 - x0) * weight;
+        return;
+    }
+    coverage[first] += (first as f64 + 1.0 - x0) * weight;
+    for c in coverage[first + 1..last].iter_mut() {
+        *c += weight;
+    }
+    if last < coverage.len() {
+        coverage[last] += (x1 - last as f64) * weight;
+    }
+}
+
+impl RenderTarget for Raster {
+    fn render(&mut self, command: &DrawCommand) {
+        self.geometry.render(command);
+        let shapes = std::mem::take(&mut self.geometry.shapes);
+        for shape in shapes[self.painted..].iter() {
+            self.paint(shape);
+        }
+        self.painted = shapes.len();
+        self.geometry.shapes = shapes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raster() -> Raster {
+        Raster::new(20, 10, Viewport { x0: 0.0, x1: 20.0, y0: 0.0, y1: 10.0 })
+    }
+
+    #[test]
+    fn background() {
+        assert_eq!(raster().pixel(3, 4), [0x11, 0x11, 0x11, 255]);
+    }
+
+    #[test]
+    fn lines() {
+        let mut raster = raster();
+        raster.render_all(&[
+            DrawCommand::BeginPath,
+            DrawCommand::SetLineWidth(2.0),
+            DrawCommand::SetStrokeStyle("#ffffff".to_string()),
+            DrawCommand::MoveTo(2.0, 5.0),
+            DrawCommand::LineTo(18.0, 5.0),
+            DrawCommand::LineTo(18.0, 0.0),
+            DrawCommand::Stroke,
+        ]);
+        // y = 5 is the boundary between rows 4 and 5
+        assert_eq!(raster.pixel(10, 4), [255, 255, 255, 255]);
+        assert_eq!(raster.pixel(10, 5), [255, 255, 255, 255]);
+        assert_eq!(raster.pixel(10, 3), [0x11, 0x11, 0x11, 255]);
+        assert_eq!(raster.pixel(1, 4), [0x11, 0x11, 0x11, 255]);
+        // the corner is joined, not painted twice
+        assert_eq!(raster.pixel(17, 5), [255, 255, 255, 255]);
+        assert_eq!(raster.pixel(18, 8), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn anti_aliasing() {
+        let mut raster = raster();
+        raster.render_all(&[
+            DrawCommand::SetFillStyle("#ffffff".to_string()),
+            DrawCommand::MoveTo(2.5, 2.0),
+            DrawCommand::LineTo(6.5, 2.0),
+            DrawCommand::LineTo(6.5, 8.0),
+            DrawCommand::LineTo(2.5, 8.0),
+            DrawCommand::Fill,
+        ]);
+        assert_eq!(raster.pixel(4, 5), [255, 255, 255, 255]);
+        let half = ((0x11 as f64 + 255.0) / 2.0).round() as u8;
+        assert_eq!(raster.pixel(2, 5), [half, half, half, 255]);
+        assert_eq!(raster.pixel(6, 5), [half, half, half, 255]);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn png() {
+        let mut raster = raster();
+        raster.render_all(&[
+            DrawCommand::SetStrokeStyle("#ff0000".to_string()),
+            DrawCommand::MoveTo(0.0, 0.0),
+            DrawCommand::LineTo(20.0, 10.0),
+            DrawCommand::Stroke,
+        ]);
+        let bytes = raster.to_png().unwrap();
+        let mut reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (20, 10));
+        assert_eq!(pixels, raster.pixels);
+    }
+}
@@ -0,0 +1,498 @@
+use futures::stream::{Stream, StreamExt};
+use std::{f64::consts::PI, pin::Pin};
+use wasm_bindgen::prelude::*;
+
+use crate::draw::*;
+
+// arcs are traced in straight steps of at most this many radians by targets
+// that cannot draw them directly
+const ARC_STEP: f64 = PI / 36.0;
+
+// anything that can carry out drawing commands
+pub trait RenderTarget {
+    fn render(&mut self, command: &DrawCommand);
+
+    fn render_all(&mut self, commands: &[DrawCommand]) {
+        for command in commands.iter() {
+            self.render(command);
+        }
+    }
+}
+
+pub async fn render_stream(target: &mut impl RenderTarget,
+                           commands: &mut Pin<Box<dyn Stream<Item=DrawCommand>>>) {
+    while let Some(command) = commands.next().await {
+        target.render(&command);
+    }
+}
+
+// a 2d canvas showing `viewport`, stretched to fill the whole canvas
+pub struct Canvas {
+    context: web_sys::CanvasRenderingContext2d,
+    delta_x: f64,
+    delta_y: f64,
+    alpha: f64,
+    beta: f64,
+}
+
+impl Canvas {
+    pub fn new(context: web_sys::CanvasRenderingContext2d, viewport: Viewport) -> Self {
+        let canvas = context.canvas().expect("canvas missing!");
+        let (width, height) = (canvas.width() as f64, canvas.height() as f64);
+        let Viewport { x0, x1, y0, y1 } = viewport;
+        let alpha = width / (x1 - x0);
+        let beta = height / (y0 - y1);
+        Self { context, delta_x: x0, delta_y: y1, alpha, beta }
+    }
+}
+
+impl RenderTarget for Canvas {
+    fn render(&mut self, command: &DrawCommand) {
+        match command {
+            DrawCommand::BeginPath => {
+                self.context.begin_path();
+            }
+            DrawCommand::SetLineWidth(lw) => {
+                self.context.set_line_width(*lw);
+            }
+            DrawCommand::SetStrokeStyle(s) => {
+                self.context.set_stroke_style(&JsValue::from_str(s));
+            }
+            DrawCommand::SetFillStyle(s) => {
+                self.context.set_fill_style(&JsValue::from_str(s));
+            }
+            DrawCommand::MoveTo(x, y) => {
+                let x = (*x - self.delta_x) * self.alpha;
+                let y = (*y - self.delta_y) * self.beta;
+                self.context.move_to(x, y);
+            }
+            DrawCommand::LineTo(x, y) => {
+                let x = (*x - self.delta_x) * self.alpha;
+                let y = (*y - self.delta_y) * self.beta;
+                self.context.line_to(x, y);
+            }
+            DrawCommand::Arc(x, y, radius, start, end) => {
+                let x = (*x - self.delta_x) * self.alpha;
+                let y = (*y - self.delta_y) * self.beta;
+                // flipping an axis mirrors the angles and the direction
+                let (sx, sy) = (self.alpha.signum(), self.beta.signum());
+                let start_angle = (sy * start.sin()).atan2(sx * start.cos());
+                let end_angle = start_angle + (end - start) * sx * sy;
+                let _ = self.context.arc_with_anticlockwise(
+                    x, y, radius * self.alpha.abs(), start_angle, end_angle, end_angle < start_angle
+                );
+            }
+            DrawCommand::ClosePath => {
+                self.context.close_path();
+            }
+            DrawCommand::Stroke => {
+                self.context.stroke();
+            }
+            DrawCommand::Fill => {
+                self.context.fill();
+            }
+        }
+    }
+}
+
+pub type Point = (f64, f64);
+
+// rounded to `precision` digits, without trailing zeros, for the text
+// formats
+pub(crate) fn number(value: f64, precision: usize) -> String {
+    let s = format!("{:.*}", precision, value);
+    let s = if s.contains('.') { s.trim_end_matches('0').trim_end_matches('.') } else { &s };
+    match s {
+        "-0" => "0".to_string(),
+        s => s.to_string(),
+    }
+}
+
+// `#rgb` or `#rrggbb`
+pub(crate) fn parse_color(style: &str) -> Option<[u8; 3]> {
+    let hex = style.strip_prefix('#')?;
+    let digit = |i: usize, n: usize| u8::from_str_radix(hex.get(i..i + n)?, 16).ok();
+    match hex.len() {
+        3 => Some([digit(0, 1)? * 17, digit(1, 1)? * 17, digit(2, 1)? * 17]),
+        6 => Some([digit(0, 2)?, digit(2, 2)?, digit(4, 2)?]),
+        _ => None,
+    }
+}
+
+// maps drawing coordinates onto a page, in whatever units the page is
+// measured in
+pub(crate) struct Placement {
+    scale: f64,
+    dx: f64,
+    dy: f64,
+    x0: f64,
+    y0: f64,
+}
+
+impl Placement {
+    // fits `viewport`, or the bounding box of the drawing, into a width by
+    // height area whose corner is at the origin, keeping its proportions
+    pub(crate) fn new(geometry: &Geometry, viewport: Option<Viewport>, width: f64, height: f64) -> Self {
+        let Viewport { x0, x1, y0, y1 } = viewport
+            .or_else(|| geometry.bounds())
+            .unwrap_or(Viewport { x0: 0.0, x1: 0.0, y0: 0.0, y1: 0.0 });
+        let (w, h) = (x1 - x0, y1 - y0);
+        let scale = match (w > 0.0, h > 0.0) {
+            (true, true) => (width / w).min(height / h),
+            (true, false) => width / w,
+            (false, true) => height / h,
+            (false, false) => 1.0,
+        };
+        Self { scale, dx: (width - w * scale) / 2.0, dy: (height - h * scale) / 2.0, x0, y0 }
+    }
+
+    // with y up
+    pub(crate) fn place(&self, (x, y): Point) -> Point {
+        ((x - self.x0) * self.scale + self.dx, (y - self.y0) * self.scale + self.dy)
+    }
+}
+
+// where a pen plotter draws
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlotArea {
+    // the region plotted. defaults to the bounding box of the drawing
+    pub viewport: Option<Viewport>,
+    // size of the plotting area in millimeters. the region is scaled to
+    // fit inside it without distortion, and centered
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Default for PlotArea {
+    // an A4 sheet in landscape
+    fn default() -> Self {
+        Self { viewport: None, width: 297.0, height: 210.0 }
+    }
+}
+
+impl PlotArea {
+    // in plotter units, `units` to the millimeter
+    pub(crate) fn placement(&self, geometry: &Geometry, units: f64) -> Placement {
+        Placement::new(geometry, self.viewport, self.width * units, self.height * units)
+    }
+}
+
+// a line a pen plotter draws, in its own coordinates
+pub(crate) struct PenLine<'a, P> {
+    pub(crate) points: Vec<P>,
+    pub(crate) color: &'a str,
+    // false when the line carries on from where the last one ended, so it
+    // is drawn without lifting the pen
+    pub(crate) lift: bool,
+}
+
+// the outlines of the drawing for a pen plotter, with every point put
+// through `place`. lines meet when their placed points are equal
+pub(crate) fn pen_lines<P: Clone + PartialEq>(geometry: &Geometry, place: impl Fn(Point) -> P) -> Vec<PenLine<'_, P>> {
+    let mut result: Vec<PenLine<P>> = Vec::new();
+    for (line, color) in outlines(geometry) {
+        let points: Vec<P> = line.into_iter().map(&place).collect();
+        let lift = result.last().and_then(|last| last.points.last()) != points.first();
+        result.push(PenLine { points, color, lift });
+    }
+    result
+}
+
+// a polyline as a list of points, repeating the first at the end when the
+// line is closed
+fn traced(points: &[Point], closed: bool) -> Vec<Point> {
+    let mut result = points.to_vec();
+    if closed && points.len() > 2 {
+        result.push(points[0]);
+    }
+    result
+}
+
+// every stroke and the outline of every fill as open lines, with their
+// colors, in the order they were drawn
+pub(crate) fn outlines(geometry: &Geometry) -> Vec<(Vec<Point>, &str)> {
+    let mut result = Vec::new();
+    for shape in geometry.shapes.iter() {
+        match shape {
+            Shape::Polyline(polyline) => {
+                result.push((traced(&polyline.points, polyline.closed), polyline.color.as_str()));
+            }
+            Shape::Fill(fill) => {
+                result.extend(fill.rings.iter().map(|ring| (traced(ring, true), fill.color.as_str())));
+            }
+        }
+    }
+    result
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polyline {
+    pub points: Vec<Point>,
+    // the last point joins back up with the first
+    pub closed: bool,
+    pub width: f64,
+    pub color: String,
+}
+
+// the area inside the rings, by the nonzero rule like the canvas
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fill {
+    pub rings: Vec<Vec<Point>>,
+    pub color: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    Polyline(Polyline),
+    Fill(Fill),
+}
+
+#[derive(Clone, Debug)]
+struct Subpath {
+    points: Vec<Point>,
+    closed: bool,
+}
+
+// collects what a canvas would paint as plain shapes, in the order they are
+// painted and in the coordinates of the drawing commands. it keeps the same
+// path and style state as a canvas, starting from the canvas defaults, and
+// traces arcs in straight steps
+#[derive(Clone, Debug)]
+pub struct Geometry {
+    pub shapes: Vec<Shape>,
+    path: Vec<Subpath>,
+    width: f64,
+    stroke: String,
+    fill: String,
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Self {
+            shapes: Vec::new(),
+            path: Vec::new(),
+            width: 1.0,
+            stroke: "#000000".to_string(),
+            fill: "#000000".to_string(),
+        }
+    }
+}
+
+impl Geometry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn polylines(&self) -> impl Iterator<Item=&Polyline> {
+        self.shapes.iter().filter_map(|shape| match shape {
+            Shape::Polyline(polyline) => Some(polyline),
+            _ => None,
+        })
+    }
+
+    pub fn fills(&self) -> impl Iterator<Item=&Fill> {
+        self.shapes.iter().filter_map(|shape| match shape {
+            Shape::Fill(fill) => Some(fill),
+            _ => None,
+        })
+    }
+
+    // the smallest region holding every point of every shape, if there
+    // are any
+    pub fn bounds(&self) -> Option<Viewport> {
+        let mut points = self.shapes.iter().flat_map(|shape| -> Box<dyn Iterator<Item=&Point>> {
+            match shape {
+                Shape::Polyline(polyline) => Box::new(polyline.points.iter()),
+                Shape::Fill(fill) => Box::new(fill.rings.iter().flatten()),
+            }
+        });
+        let &(x, y) = points.next()?;
+        Some(points.fold(Viewport { x0: x, x1: x, y0: y, y1: y }, |v, &(x, y)| Viewport {
+            x0: v.x0.min(x), x1: v.x1.max(x), y0: v.y0.min(y), y1: v.y1.max(y),
+        }))
+    }
+
+    // repeated points draw nothing and are left out
+    fn line_to(&mut self, point: Point) {
+        match self.path.last_mut() {
+            Some(subpath) if subpath.points.last() == Some(&point) => {}
+            Some(subpath) => subpath.points.push(point),
+            None => self.path.push(Subpath { points: vec![point], closed: false }),
+        }
+    }
+}
+
+impl RenderTarget for Geometry {
+    fn render(&mut self, command: &DrawCommand) {
+        match command {
+            DrawCommand::BeginPath => {
+                self.path.clear();
+            }
+            DrawCommand::SetLineWidth(width) => {
+                self.width = *width;
+            }
+            DrawCommand::SetStrokeStyle(style) => {
+                self.stroke = style.clone();
+            }
+            DrawCommand::SetFillStyle(style) => {
+                self.fill = style.clone();
+            }
+            DrawCommand::MoveTo(x, y) => {
+                self.path.push(Subpath { points: vec![(*x, *y)], closed: false });
+            }
+            DrawCommand::LineTo(x, y) => {
+                self.line_to((*x, *y));
+            }
+            DrawCommand::Arc(cx, cy, radius, start, end) => {
+                // like the canvas, a line joins the current point to the
+                // start of the arc
+                let steps = ((end - start).abs() / ARC_STEP).ceil().max(1.0) as u32;
+                for i in 0..=steps {
+                    let angle = start + (end - start) * i as f64 / steps as f64;
+                    self.line_to((cx + radius * angle.cos(), cy + radius * angle.sin()));
+                }
+            }
+            DrawCommand::ClosePath => {
+                if let Some(subpath) = self.path.last_mut() {
+                    subpath.closed = true;
+                    let start = subpath.points[0];
+                    self.path.push(Subpath { points: vec![start], closed: false });
+                }
+            }
+            DrawCommand::Stroke => {
+                for subpath in self.path.iter().filter(|s| s.points.len() > 1) {
+                    self.shapes.push(Shape::Polyline(Polyline {
+                        points: subpath.points.clone(),
+                        closed: subpath.closed,
+                        width: self.width,
+                        color: self.stroke.clone(),
+                    }));
+                }
+            }
+            DrawCommand::Fill => {
+                let rings: Vec<Vec<Point>> = self.path.iter()
+                    .filter(|s| s.points.len() > 2)
+                    .map(|s| s.points.clone())
+                    .collect();
+                if !rings.is_empty() {
+                    self.shapes.push(Shape::Fill(Fill { rings, color: self.fill.clone() }));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::turtle::*;
+    use std::f64::consts::TAU;
+
+    // a red stroke, a blue fill whose outline starts away from where the
+    // stroke ends, and a green stroke that carries on from the outline.
+    // for the tests of the page and plotter formats
+    pub(crate) fn drawing() -> Geometry {
+        let mut geometry = Geometry::new();
+        geometry.render_all(&[
+            DrawCommand::SetLineWidth(4.0),
+            DrawCommand::SetStrokeStyle("#ff0000".to_string()),
+            DrawCommand::MoveTo(0.0, 0.0),
+            DrawCommand::LineTo(10.0, 5.0),
+            DrawCommand::Stroke,
+            DrawCommand::BeginPath,
+            DrawCommand::SetFillStyle("#0000ff".to_string()),
+            DrawCommand::MoveTo(0.0, 0.0),
+            DrawCommand::LineTo(10.0, 0.0),
+            DrawCommand::LineTo(10.0, 5.0),
+            DrawCommand::Fill,
+            DrawCommand::BeginPath,
+            DrawCommand::SetStrokeStyle("#00ff00".to_string()),
+            DrawCommand::MoveTo(0.0, 0.0),
+            DrawCommand::LineTo(0.0, 5.0),
+            DrawCommand::Stroke,
+        ]);
+        geometry
+    }
+
+    fn program(commands: Vec<TurtleCommand>) -> TurtleProgram {
+        TurtleProgram::new(Turtle::new(Pen { color: (1.0, 0.0, 0.0), width: 2.0, state: PenState::Down }), commands)
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("#ff8000"), Some([255, 128, 0]));
+        assert_eq!(parse_color("#0f0"), Some([0, 255, 0]));
+        assert_eq!(parse_color("red"), None);
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(number(10.0, 2), "10");
+        assert_eq!(number(-1.23456, 3), "-1.235");
+        assert_eq!(number(-0.0001, 2), "0");
+        assert_eq!(number(1.5, 0), "2");
+    }
+
+    #[test]
+    fn lines_for_plotters() {
+        let geometry = drawing();
+        let lines = pen_lines(&geometry, |(x, y)| (x as i64, y as i64));
+        let summary: Vec<_> = lines.iter().map(|line| (line.points.len(), line.color, line.lift)).collect();
+        assert_eq!(summary, [(2, "#ff0000", true), (4, "#0000ff", true), (2, "#00ff00", false)]);
+        let area = PlotArea { width: 100.0, height: 100.0, ..PlotArea::default() };
+        assert_eq!(area.placement(&drawing(), 10.0).place((10.0, 5.0)), (1000.0, 750.0));
+    }
+
+    #[test]
+    fn strokes() {
+        let mut geometry = Geometry::new();
+        program(vec![
+            TurtleCommand::Move(10.0),
+            TurtleCommand::PenUp,
+            TurtleCommand::Move(10.0),
+            TurtleCommand::PenDown,
+            TurtleCommand::Move(10.0),
+            TurtleCommand::Width(4.0),
+            TurtleCommand::Move(10.0),
+        ]).render(&mut geometry).unwrap();
+        let polyline = |points: Vec<Point>, width: f64| Shape::Polyline(Polyline {
+            points, closed: false, width, color: "#ff0000".to_string(),
+        });
+        assert_eq!(geometry.shapes, vec![
+            polyline(vec![(0.0, 0.0), (10.0, 0.0)], 2.0),
+            polyline(vec![(20.0, 0.0), (30.0, 0.0)], 2.0),
+            polyline(vec![(30.0, 0.0), (40.0, 0.0)], 4.0),
+        ]);
+    }
+
+    #[test]
+    fn fills_and_arcs() {
+        let mut geometry = Geometry::new();
+        geometry.render_all(&[
+            DrawCommand::SetFillStyle("#00ff00".to_string()),
+            DrawCommand::MoveTo(0.0, 0.0),
+            DrawCommand::LineTo(1.0, 0.0),
+            DrawCommand::LineTo(1.0, 1.0),
+            DrawCommand::ClosePath,
+            DrawCommand::Fill,
+            DrawCommand::Stroke,
+            DrawCommand::BeginPath,
+            DrawCommand::MoveTo(2.0, 0.0),
+            DrawCommand::Arc(0.0, 0.0, 2.0, 0.0, TAU),
+            DrawCommand::Stroke,
+        ]);
+        assert_eq!(geometry.fills().collect::<Vec<_>>(), vec![&Fill {
+            rings: vec![vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)]],
+            color: "#00ff00".to_string(),
+        }]);
+        let polylines: Vec<&Polyline> = geometry.polylines().collect();
+        assert_eq!(polylines.len(), 2);
+        assert!(polylines[0].closed);
+        assert_eq!((polylines[0].width, polylines[0].color.as_str()), (1.0, "#000000"));
+        assert_eq!(geometry.bounds(), Some(Viewport { x0: -2.0, x1: 2.0, y0: -2.0, y1: 2.0 }));
+        assert_eq!(Geometry::new().bounds(), None);
+        let circle = &polylines[1].points;
+        assert_eq!(circle.len(), 73);
+        assert!(circle.iter().all(|(x, y)| ((x * x + y * y).sqrt() - 2.0).abs() < 1e-9));
+    }
+}
@@ -0,0 +1,182 @@
+use std::fmt::Write;
+
+use crate::draw::*;
+use crate::render::*;
+
+#[derive(Clone, Debug)]
+pub struct SvgOptions {
+    // the region shown. defaults to the bounding box of the drawing
+    pub viewport: Option<Viewport>,
+    // width and height of the image. defaults to the size of the region
+    pub size: Option<(f64, f64)>,
+    // digits after the decimal point in coordinates
+    pub precision: usize,
+    // painted behind the drawing, if any
+    pub background: Option<String>,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self { viewport: None, size: None, precision: 2, background: Some(BACKGROUND.to_string()) }
+    }
+}
+
+// path data for `points`, flipping y so it points down the page
+fn path_data(d: &mut String, points: &[Point], closed: bool, precision: usize) {
+    for (i, (x, y)) in points.iter().enumerate() {
+        let _ = write!(d, "{}{} {}", if i == 0 { "M" } else { "L" }, number(*x, precision), number(-y, precision));
+    }
+    if closed {
+        d.push('Z');
+    }
+}
+
+// `value` escaped to go inside a quoted xml attribute
+fn escape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+// a standalone svg document. line widths are in pixels of the image, the
+// way the canvas draws them, whatever the scale of the drawing. polylines
+// that share a stroke style go in one path, placed where the style is first
+// used, and each fill gets a path of its own
+pub fn svg(geometry: &Geometry, options: &SvgOptions) -> String {
+    let Viewport { x0, x1, y0, y1 } = options.viewport
+        .or_else(|| geometry.bounds())
+        .unwrap_or(Viewport { x0: 0.0, x1: 0.0, y0: 0.0, y1: 0.0 });
+    // a straight line still needs some room
+    let (x0, x1) = if x1 > x0 { (x0, x1) } else { (x0 - 0.5, x0 + 0.5) };
+    let (y0, y1) = if y1 > y0 { (y0, y1) } else { (y0 - 0.5, y0 + 0.5) };
+    let (width, height) = options.size.unwrap_or((x1 - x0, y1 - y0));
+    let precision = options.precision;
+    let n = |v: f64| number(v, precision);
+
+    // path data with the stroke style, or just the fill color
+    let mut paths: Vec<(String, &str, Option<f64>)> = Vec::new();
+    for shape in geometry.shapes.iter() {
+        match shape {
+            Shape::Polyline(polyline) => {
+                let style = (polyline.color.as_str(), Some(polyline.width));
+                let i = match paths.iter().position(|(_, color, width)| (*color, *width) == style) {
+                    Some(i) => i,
+                    None => {
+                        paths.push((String::new(), style.0, style.1));
+                        paths.len() - 1
+                    }
+                };
+                path_data(&mut paths[i].0, &polyline.points, polyline.closed, precision);
+            }
+            Shape::Fill(fill) => {
+                let mut d = String::new();
+                for ring in fill.rings.iter() {
+                    path_data(&mut d, ring, true, precision);
+                }
+                paths.push((d, &fill.color, None));
+            }
+        }
+    }
+
+    let mut result = String::new();
+    let _ = writeln!(result, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        result,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="{} {} {} {}">"#,
+        n(width), n(height), n(x0), n(-y1), n(x1 - x0), n(y1 - y0),
+    );
+    if let Some(background) = &options.background {
+        let _ = writeln!(
+            result,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+            n(x0), n(-y1), n(x1 - x0), n(y1 - y0), escape(background),
+        );
+    }
+    for (d, color, width) in paths.iter() {
+        match width {
+            Some(width) => {
+                let _ = writeln!(
+                    result,
+                    r#"<path d="{}" fill="none" stroke="{}" stroke-width="{}" vector-effect="non-scaling-stroke"/>"#,
+                    d, escape(color), n(*width),
+                );
+            }
+            None => {
+                let _ = writeln!(result, r#"<path d="{}" fill="{}"/>"#, d, escape(color));
+            }
+        }
+    }
+    result.push_str("</svg>\n");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn document() {
+        let mut geometry = Geometry::new();
+        geometry.render_all(&[
+            DrawCommand::SetLineWidth(2.0),
+            DrawCommand::SetStrokeStyle("#ff0000".to_string()),
+            DrawCommand::MoveTo(0.0, 0.0),
+            DrawCommand::LineTo(10.0, 5.0),
+            DrawCommand::Stroke,
+            DrawCommand::BeginPath,
+            DrawCommand::SetFillStyle("#00ff00".to_string()),
+            DrawCommand::MoveTo(0.0, 0.0),
+            DrawCommand::LineTo(1.0, 0.0),
+            DrawCommand::LineTo(1.0, 1.0),
+            DrawCommand::Fill,
+            DrawCommand::BeginPath,
+            DrawCommand::MoveTo(10.0, 0.0),
+            DrawCommand::LineTo(0.0, 5.0),
+            DrawCommand::Stroke,
+        ]);
+        let actual = svg(&geometry, &SvgOptions { background: None, ..SvgOptions::default() });
+        assert_eq!(actual, concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"10\" height=\"5\" viewBox=\"0 -5 10 5\">\n",
+            "<path d=\"M0 0L10 -5M10 0L0 -5\" fill=\"none\" stroke=\"#ff0000\" stroke-width=\"2\" vector-effect=\"non-scaling-stroke\"/>\n",
+            "<path d=\"M0 0L1 0L1 -1Z\" fill=\"#00ff00\"/>\n",
+            "</svg>\n",
+        ));
+
+        let viewport = Viewport { x0: -100.0, x1: 100.0, y0: -50.0, y1: 50.0 };
+        let actual = svg(&geometry, &SvgOptions { viewport: Some(viewport), size: Some((400.0, 200.0)), ..SvgOptions::default() });
+        assert!(actual.contains("width=\"400\" height=\"200\" viewBox=\"-100 -50 200 100\""));
+        assert!(actual.contains("<rect x=\"-100\" y=\"-50\" width=\"200\" height=\"100\" fill=\"#111111\"/>"));
+    }
+
+    #[test]
+    fn escaped_styles() {
+        let mut geometry = Geometry::new();
+        geometry.render_all(&[
+            DrawCommand::SetStrokeStyle("url(\"#a\")".to_string()),
+            DrawCommand::MoveTo(0.0, 0.0),
+            DrawCommand::LineTo(1.0, 1.0),
+            DrawCommand::Stroke,
+            DrawCommand::BeginPath,
+            DrawCommand::SetFillStyle("<red & 'blue'>".to_string()),
+            DrawCommand::MoveTo(0.0, 0.0),
+            DrawCommand::LineTo(1.0, 0.0),
+            DrawCommand::LineTo(1.0, 1.0),
+            DrawCommand::Fill,
+        ]);
+        let actual = svg(&geometry, &SvgOptions { background: Some("\"/><script/>".to_string()), ..SvgOptions::default() });
+        assert!(actual.contains("fill=\"&quot;/&gt;&lt;script/&gt;\"/>"));
+        assert!(actual.contains("stroke=\"url(&quot;#a&quot;)\""));
+        assert!(actual.contains("fill=\"&lt;red &amp; &apos;blue&apos;&gt;\"/>"));
+        assert!(!actual.contains("<script"));
+    }
+}
@@ -1,8 +1,6 @@
-use wasm_bindgen_futures;
-use web_sys;
 use futures::{
     future::{self},
-    stream::{self, Stream, StreamExt},
+    stream::{self, Stream, StreamExt, TryStreamExt},
 };
 use std::{
     cell::RefCell,
@@ -12,41 +10,285 @@ use std::{
 };
 
 use crate::draw::*;
+use crate::random::*;
+use crate::render::*;
+use crate::vector::*;
+
+// arcs that cannot be drawn as canvas arcs are traced in steps of at most
+// this many degrees
+const ARC_STEP: f64 = 10.0;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum PenState { Down, Up }
 
+// red, green and blue components between 0 and 1
+pub type Color = (f64, f64, f64);
+
+pub fn css_color((r, g, b): Color) -> String {
+    let component = |c: f64| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", component(r), component(g), component(b))
+}
+
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pen {
-    pub color: (f64, f64, f64),
+    pub color: Color,
     pub width: f64,
     pub state: PenState
 }
 
 impl Pen {
     fn run(&self) -> Vec<DrawCommand> {
-        let mut result = Vec::new();
-        result.push(DrawCommand::SetLineWidth(self.width));
-        result.push(DrawCommand::SetStrokeStyle("red".to_string()));//TODO
+        vec![
+            DrawCommand::SetLineWidth(self.width),
+            DrawCommand::SetStrokeStyle(css_color(self.color)),
+        ]
+    }
+
+    // a canvas path is stroked with a single style, so changing the style
+    // means finishing the current path and starting a new one at `at`
+    fn restroke(&self, at: (f64, f64)) -> Vec<DrawCommand> {
+        let mut result = vec![DrawCommand::Stroke, DrawCommand::BeginPath];
+        result.append(&mut self.run());
+        result.push(DrawCommand::MoveTo(at.0, at.1));
         result
     }
 
-    fn run_as_stream(&self) -> Pin<Box<dyn Stream<Item=DrawCommand>>> {
-        stream::iter(self.run()).boxed_local()
+    // fills the path drawn by `shape` in the pen color, then carries on
+    // stroking from `at`
+    fn fill(&self, mut shape: Vec<DrawCommand>, at: (f64, f64)) -> Vec<DrawCommand> {
+        let mut result = vec![
+            DrawCommand::Stroke,
+            DrawCommand::BeginPath,
+            DrawCommand::SetFillStyle(css_color(self.color)),
+        ];
+        result.append(&mut shape);
+        result.push(DrawCommand::Fill);
+        result.push(DrawCommand::BeginPath);
+        result.append(&mut self.run());
+        result.push(DrawCommand::MoveTo(at.0, at.1));
+        result
+    }
+
+    fn same_stroke(&self, other: &Pen) -> bool {
+        self.color == other.color && self.width == other.width
+    }
+}
+
+// orthographic projection of turtle space onto the drawing plane. the world
+// is rotated `azimuth` degrees about the z axis and then tilted `elevation`
+// degrees about the x axis, bringing the z axis up the screen. the default
+// looks straight down the z axis, so flat drawings come out unchanged, and
+// an elevation of 90 is a side view
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Projection {
+    pub azimuth: f64,
+    pub elevation: f64,
+}
+
+// ABOP's tropism: after every move the heading bends towards `direction` by
+// `susceptibility * |H x T|` radians, so branches droop under gravity or
+// reach for the light. it applies to the whole program, every branch alike
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tropism {
+    pub direction: Vec3,
+    pub susceptibility: f64,
+}
+
+// random variation of every rotation and move, as fractions of the
+// commanded angle or distance. a `RandomTurn` or `RandomMove` overrides it
+// with its own spread. `seed` picks the sequence, so the same program always
+// draws the same picture
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Jitter {
+    pub angle: f64,
+    pub length: f64,
+    pub seed: u64,
+}
+
+// program wide settings that stay fixed while the turtle runs
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Settings {
+    pub projection: Projection,
+    // colors selected by `ColorIndex`
+    pub palette: Vec<Color>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tropism: Option<Tropism>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub jitter: Option<Jitter>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub limits: Limits,
+}
+
+// bounds that keep a runaway program from exhausting memory or time
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Limits {
+    // the most turtles `Push` may save at once
+    pub stack_depth: usize,
+    // the most commands a program may run, counting each pass through the
+    // body of a `Repeat`
+    pub commands: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self { stack_depth: 100_000, commands: 100_000_000 }
+    }
+}
+
+// `command` is the index of the offending command in the program. when the
+// problem is inside a `Repeat` it is the index of the outermost `Repeat`
+#[derive(Clone, Debug, PartialEq)]
+pub enum TurtleError {
+    // a `Pop` without a matching `Push`
+    StackUnderflow { command: usize },
+    StackOverflow { command: usize, limit: usize },
+    // the turtle's position or frame is infinite or NaN
+    NonFinite { command: usize },
+    CommandBudgetExceeded { command: usize, budget: usize },
+    // a `ColorIndex` past the end of the palette
+    PaletteIndex { command: usize, index: u32, palette: usize },
+    // a `Vertex` or `PolygonEnd` without an open polygon
+    NoOpenPolygon { command: usize },
+    // a `PolygonBegin` still open at the end of the program
+    UnclosedPolygon { command: usize },
+}
+
+impl TurtleError {
+    pub fn command(&self) -> usize {
+        match self {
+            TurtleError::StackUnderflow { command } |
+            TurtleError::StackOverflow { command, .. } |
+            TurtleError::NonFinite { command } |
+            TurtleError::CommandBudgetExceeded { command, .. } |
+            TurtleError::PaletteIndex { command, .. } |
+            TurtleError::NoOpenPolygon { command } |
+            TurtleError::UnclosedPolygon { command } => *command,
+        }
+    }
+}
+
+impl fmt::Display for TurtleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TurtleError::StackUnderflow { command } =>
+                write!(f, "command {}: cannot pop an empty stack", command),
+            TurtleError::StackOverflow { command, limit } =>
+                write!(f, "command {}: more than {} turtles pushed", command, limit),
+            TurtleError::NonFinite { command } =>
+                write!(f, "command {}: the turtle left finite space", command),
+            TurtleError::CommandBudgetExceeded { command, budget } =>
+                write!(f, "command {}: ran more than {} commands", command, budget),
+            TurtleError::PaletteIndex { command, index, palette } =>
+                write!(f, "command {}: no color {} in a palette of {}", command, index, palette),
+            TurtleError::NoOpenPolygon { command } =>
+                write!(f, "command {}: no polygon is open", command),
+            TurtleError::UnclosedPolygon { command } =>
+                write!(f, "command {}: the polygon is never ended", command),
+        }
+    }
+}
+
+impl Settings {
+    pub fn seed(&self) -> u64 {
+        self.jitter.map(|j| j.seed).unwrap_or_default()
+    }
+
+    fn angle_spread(&self, angle: f64) -> f64 {
+        self.jitter.map(|j| (j.angle * angle).abs()).unwrap_or_default()
+    }
+
+    fn length_spread(&self, distance: f64) -> f64 {
+        self.jitter.map(|j| (j.length * distance).abs()).unwrap_or_default()
+    }
+}
+
+// somewhere between `value - spread` and `value + spread`. the generator is
+// only drawn from when there is something to vary, so programs without any
+// jitter leave it untouched
+fn vary(value: f64, spread: f64, random: &mut Random) -> f64 {
+    if spread == 0.0 {
+        value
+    } else {
+        value + spread * random.uniform()
+    }
+}
+
+impl Projection {
+    pub fn project(&self, p: Vec3) -> (f64, f64) {
+        let (sin, cos) = self.azimuth.to_radians().sin_cos();
+        let (x, y) = (p.x * cos - p.y * sin, p.x * sin + p.y * cos);
+        let (sin, cos) = self.elevation.to_radians().sin_cos();
+        (x, y * cos + p.z * sin)
     }
 }
 
+// the turtle's frame follows ABOP: `heading` points forward, `left` to the
+// turtle's left and `up` completes the right handed frame. all rotations are
+// right handed about the named axis, so a positive turn (about `up`) moves
+// the heading towards `left`, a positive pitch (about `left`) moves it down
+// and a positive roll (about `heading`) moves `left` towards `up`
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Turtle {
-    pub location: (f64, f64),
-    pub orientation: f64,
+    pub location: Vec3,
+    pub heading: Vec3,
+    pub left: Vec3,
+    pub up: Vec3,
+    // multiplies the distance of every move
+    pub scale: f64,
     pub pen: Pen
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum TurtleCommand {
     Move(f64),
     Turn(f64),
+    // a move or turn by a random amount up to the second value either side
+    // of the first
+    RandomMove(f64, f64),
+    RandomTurn(f64, f64),
+    // a turn about the up vector, the same as turn. the 3d name for it
+    Yaw(f64),
+    Pitch(f64),
+    Roll(f64),
+    TurnAround,
+    Color(f64, f64, f64),
+    ColorIndex(u32),
+    Width(f64),
+    ScaleWidth(f64),
+    Scale(f64),
+    // polygons live on their own stack, separate from `Push` and `Pop`, so
+    // vertices recorded inside a branch still belong to the open polygon.
+    // polygons can nest, `PolygonEnd` fills the innermost one
+    PolygonBegin,
+    Vertex,
+    PolygonEnd,
+    // radius and angle in degrees. the sign of the angle alone picks the
+    // side the arc bends to
+    Arc(f64, f64),
+    Circle(f64),
+    Dot(f64),
+    // absolute motion in the ground plane, z = 0, ignoring the length scale.
+    // `Goto` and `Home` draw like `Move` when the pen is down. `Heading` and
+    // `Face` point the turtle along the plane with up along the z axis,
+    // undoing any pitch or roll. the position and frame they set are turtle
+    // state like any other, so a `Pop` returns to wherever the turtle was at
+    // the matching `Push`
+    Goto(f64, f64),
+    // degrees counter clockwise from the x axis
+    Heading(f64),
+    Home,
+    Face(f64, f64),
     PenDown,
     PenUp,
     Repeat(u32, Vec<TurtleCommand>),
@@ -55,62 +297,500 @@ pub enum TurtleCommand {
 }
 
 impl Turtle {
-    pub fn run(&mut self, commands: &Vec<TurtleCommand>, stack: &mut Vec<Turtle>) -> Vec<DrawCommand> {
+    // at the origin, heading along the x axis with up along the z axis
+    pub fn new(pen: Pen) -> Self {
+        Self { location: Vec3::default(), heading: Vec3::X, left: Vec3::Y, up: Vec3::Z, scale: 1.0, pen }
+    }
+
+    // where a move of `distance` would end up
+    fn ahead(&self, distance: f64) -> Vec3 {
+        self.location + self.heading * (distance * self.scale)
+    }
+
+    fn turn(&mut self, angle: f64) {
+        (self.heading, self.left) = rotate_pair(self.heading, self.left, angle);
+    }
+
+    fn pitch(&mut self, angle: f64) {
+        (self.up, self.heading) = rotate_pair(self.up, self.heading, angle);
+    }
+
+    fn roll(&mut self, angle: f64) {
+        (self.left, self.up) = rotate_pair(self.left, self.up, angle);
+    }
+
+    // rotates the whole frame so the heading moves towards the tropism
+    // vector. a heading parallel to it stays put
+    fn bend(&mut self, tropism: Option<Tropism>) {
+        let Some(tropism) = tropism else { return };
+        let axis = self.heading.cross(tropism.direction);
+        let length = axis.length();
+        if length < 1e-12 {
+            return;
+        }
+        let (axis, angle) = (axis * (1.0 / length), (tropism.susceptibility * length).to_degrees());
+        self.heading = rotate_about(self.heading, axis, angle);
+        self.left = rotate_about(self.left, axis, angle);
+        self.up = rotate_about(self.up, axis, angle);
+    }
+
+    fn set_heading(&mut self, angle: f64) {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        self.heading = Vec3::new(cos, sin, 0.0);
+        self.left = Vec3::new(-sin, cos, 0.0);
+        self.up = Vec3::Z;
+    }
+
+    // turns towards (x, y). nothing happens when the turtle is already there
+    fn face(&mut self, x: f64, y: f64) {
+        let (dx, dy) = (x - self.location.x, y - self.location.y);
+        if dx != 0.0 || dy != 0.0 {
+            self.set_heading(dy.atan2(dx).to_degrees());
+        }
+    }
+
+    fn go_to(&mut self, location: Vec3, settings: &Settings) -> DrawCommand {
+        self.location = location;
+        let (x, y) = settings.projection.project(location);
+        if self.pen.state == PenState::Down {
+            DrawCommand::LineTo(x, y)
+        } else {
+            DrawCommand::MoveTo(x, y)
+        }
+    }
+
+    fn set_color(&mut self, color: Color, settings: &Settings) -> Vec<DrawCommand> {
+        if color == self.pen.color {
+            return Vec::new();
+        }
+        self.pen.color = color;
+        self.pen.restroke(settings.projection.project(self.location))
+    }
+
+    fn set_width(&mut self, width: f64, settings: &Settings) -> Vec<DrawCommand> {
+        if width == self.pen.width {
+            return Vec::new();
+        }
+        self.pen.width = width;
+        self.pen.restroke(settings.projection.project(self.location))
+    }
+
+    fn end_polygon(&self, polygon: &[Vec3], settings: &Settings) -> Vec<DrawCommand> {
+        let projection = settings.projection;
+        let points: Vec<(f64, f64)> = polygon.iter()
+            .map(|p| projection.project(*p))
+            .collect();
+        if points.len() < 3 {
+            return Vec::new();
+        }
+        let mut shape = vec![DrawCommand::MoveTo(points[0].0, points[0].1)];
+        for (x, y) in points[1..].iter() {
+            shape.push(DrawCommand::LineTo(*x, *y));
+        }
+        shape.push(DrawCommand::ClosePath);
+        self.pen.fill(shape, projection.project(self.location))
+    }
+
+    // moves along a circle of `radius`, turning `angle` degrees on the way so
+    // the turtle ends up tangent to the circle. the circle is on the left for
+    // positive angles and on the right otherwise, whatever the sign of the
+    // radius. returns its center
+    fn arc(&mut self, radius: f64, angle: f64) -> Vec3 {
+        let offset = (radius * self.scale).abs() * if angle < 0.0 { -1.0 } else { 1.0 };
+        let center = self.location + self.left * offset;
+        self.turn(angle);
+        self.location = center - self.left * offset;
+        center
+    }
+
+    // the points an arc passes through, from where the turtle is
+    fn arc_points(&self, radius: f64, angle: f64) -> Vec<Vec3> {
+        let mut turtle = self.clone();
+        let steps = (angle.abs() / ARC_STEP).ceil().max(1.0);
+        let mut result = vec![turtle.location];
+        for _ in 0..steps as u32 {
+            turtle.arc(radius, angle / steps);
+            result.push(turtle.location);
+        }
+        result
+    }
+
+    // points around a circle about the turtle in the plane it turns in,
+    // ending where they start
+    fn circle_points(&self, radius: f64) -> Vec<Vec3> {
+        let radius = (radius * self.scale).abs();
+        let steps = (360.0 / ARC_STEP).ceil() as u32;
+        (0..=steps).map(|i| {
+            let t = std::f64::consts::TAU * i as f64 / steps as f64;
+            self.location + (self.heading * t.cos() + self.left * t.sin()) * radius
+        }).collect()
+    }
+
+    // the area of the unit square spanned by `heading` and `left` once
+    // projected. 1 or -1 when the turtle turns in the drawing plane, seen
+    // from above or below, and smaller when it is seen at an angle
+    fn facing(&self, projection: Projection) -> f64 {
+        let (hx, hy) = projection.project(self.heading);
+        let (lx, ly) = projection.project(self.left);
+        hx * ly - hy * lx
+    }
+
+    fn arc_commands(&mut self, radius: f64, angle: f64, settings: &Settings) -> Vec<DrawCommand> {
+        let projection = settings.projection;
+        if self.pen.state == PenState::Up {
+            self.arc(radius, angle);
+            let (x, y) = projection.project(self.location);
+            return vec![DrawCommand::MoveTo(x, y)];
+        }
+
+        let facing = self.facing(projection);
+        if (facing.abs() - 1.0).abs() < 1e-9 {
+            let (x, y) = projection.project(self.location);
+            let (cx, cy) = projection.project(self.arc(radius, angle));
+            let start = (y - cy).atan2(x - cx);
+            let end = start + facing.signum() * angle.to_radians();
+            vec![DrawCommand::Arc(cx, cy, (radius * self.scale).abs(), start, end)]
+        } else {
+            // seen at an angle the arc is part of an ellipse
+            let steps = (angle.abs() / ARC_STEP).ceil().max(1.0);
+            (0..steps as u32).map(|_| {
+                self.arc(radius, angle / steps);
+                let (x, y) = projection.project(self.location);
+                DrawCommand::LineTo(x, y)
+            }).collect()
+        }
+    }
+
+    // a circle around the turtle, in the plane it turns in. the turtle
+    // stays where it is
+    fn circle_commands(&self, radius: f64, settings: &Settings) -> Vec<DrawCommand> {
+        if self.pen.state == PenState::Up {
+            return Vec::new();
+        }
+        let projection = settings.projection;
+        let (cx, cy) = projection.project(self.location);
+
+        let mut result = if (self.facing(projection).abs() - 1.0).abs() < 1e-9 {
+            let radius = (radius * self.scale).abs();
+            vec![
+                DrawCommand::MoveTo(cx + radius, cy),
+                DrawCommand::Arc(cx, cy, radius, 0.0, std::f64::consts::TAU),
+            ]
+        } else {
+            self.circle_points(radius).into_iter().enumerate().map(|(i, p)| {
+                let (x, y) = projection.project(p);
+                if i == 0 { DrawCommand::MoveTo(x, y) } else { DrawCommand::LineTo(x, y) }
+            }).collect()
+        };
+        result.push(DrawCommand::MoveTo(cx, cy));
+        result
+    }
+
+    // a disk in the pen color, facing the viewer, whether the pen is down
+    // or not
+    fn dot_commands(&self, radius: f64, settings: &Settings) -> Vec<DrawCommand> {
+        let radius = (radius * self.scale).abs();
+        let (cx, cy) = settings.projection.project(self.location);
+        let shape = vec![
+            DrawCommand::MoveTo(cx + radius, cy),
+            DrawCommand::Arc(cx, cy, radius, 0.0, std::f64::consts::TAU),
+        ];
+        self.pen.fill(shape, (cx, cy))
+    }
+}
+
+// a straight stretch of line drawn by the turtle, in turtle space, and
+// where in the program it came from
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Segment {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub width: f64,
+    pub color: Color,
+    // how many `Push`es deep the turtle was
+    pub depth: usize,
+    // the index of the program command that drew it. commands inside a
+    // `Repeat` share the index of the `Repeat`
+    pub command: usize,
+    // the symbol that command was compiled from, when the program knows
+    pub symbol: Option<char>,
+}
+
+// a filled polygon, or a dot, in turtle space
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Polygon {
+    pub points: Vec<Vec3>,
+    pub color: Color,
+    // how many `Push`es deep the turtle was when it was filled
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub depth: usize,
+}
+
+// what a program draws, before it is projected onto the drawing plane.
+// arcs and circles are traced in straight segments
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Model {
+    pub segments: Vec<Segment>,
+    pub polygons: Vec<Polygon>,
+}
+
+impl Model {
+    // a segment in the pen's style, when the pen is down, drawn by
+    // `command` at `depth`
+    fn line(&mut self, pen: &Pen, start: Vec3, end: Vec3, (command, depth): (usize, usize)) {
+        if pen.state == PenState::Down && start != end {
+            let (width, color) = (pen.width, pen.color);
+            self.segments.push(Segment { start, end, width, color, depth, command, symbol: None });
+        }
+    }
+
+    fn path(&mut self, pen: &Pen, points: &[Vec3], origin: (usize, usize)) {
+        for pair in points.windows(2) {
+            self.line(pen, pair[0], pair[1], origin);
+        }
+    }
+}
+
+// the state of a running program: the turtle, the turtles saved by `Push`,
+// the polygons being recorded and the random number generator. both ways of
+// running a program feed it one command at a time, so they draw the same
+// thing
+#[derive(Clone, Debug)]
+pub struct Executor {
+    turtle: Turtle,
+    stack: Vec<Turtle>,
+    // the index of the command that opened each polygon, and its vertices
+    polygons: Vec<(usize, Vec<Vec3>)>,
+    random: Random,
+    settings: Settings,
+    // the index of the next command handed to `step`
+    index: usize,
+    // commands run so far, including those inside `Repeat`
+    count: usize,
+    // what has been drawn, when it is being recorded
+    model: Option<Model>,
+}
+
+impl Executor {
+    pub fn new(turtle: Turtle, settings: Settings) -> Self {
+        let random = Random::new(settings.seed());
+        Self { turtle, stack: Vec::new(), polygons: Vec::new(), random, settings, index: 0, count: 0, model: None }
+    }
+
+    // records what is drawn in turtle space as well
+    pub fn recording(mut self) -> Self {
+        self.model = Some(Model::default());
+        self
+    }
+
+    // what has been drawn so far, if it is being recorded
+    pub fn model(&self) -> Option<&Model> {
+        self.model.as_ref()
+    }
+
+    pub fn turtle(&self) -> &Turtle {
+        &self.turtle
+    }
+
+    // opens the first path at the turtle's starting point
+    pub fn start(&self) -> Vec<DrawCommand> {
+        let mut result = vec![DrawCommand::BeginPath];
+        result.append(&mut self.turtle.pen.run());
+        let (x, y) = self.settings.projection.project(self.turtle.location);
+        result.push(DrawCommand::MoveTo(x, y));
+        result
+    }
+
+    // runs the next command of the program
+    pub fn step(&mut self, command: &TurtleCommand) -> Result<Vec<DrawCommand>, TurtleError> {
         let mut result = Vec::new();
+        let outcome = self.run(command, &mut result);
+        self.index += 1;
+        outcome.map(|_| result)
+    }
 
-        for command in commands.iter() {
-            match command {
-                TurtleCommand::Move(distance) => {
-                    let angle = self.orientation;
-                    let (x, y) = self.location;
-                    let x = x + distance * angle.to_radians().cos();
-                    let y = y + distance * angle.to_radians().sin();
-                    self.location = (x, y);
-                    if self.pen.state == PenState::Down {
-                        result.push(DrawCommand::LineTo(x, y));
-                    } else {
-                        result.push(DrawCommand::MoveTo(x, y));
+    fn run(&mut self, command: &TurtleCommand, result: &mut Vec<DrawCommand>) -> Result<(), TurtleError> {
+        let index = self.index;
+        self.count += 1;
+        if self.count > self.settings.limits.commands {
+            return Err(TurtleError::CommandBudgetExceeded { command: index, budget: self.settings.limits.commands });
+        }
+
+        let turtle = &mut self.turtle;
+        let settings = &self.settings;
+        let random = &mut self.random;
+        let model = &mut self.model;
+        let start = turtle.location;
+        let origin = (index, self.stack.len());
+
+        match command {
+            TurtleCommand::Move(distance) => {
+                let distance = vary(*distance, settings.length_spread(*distance), random);
+                result.push(turtle.go_to(turtle.ahead(distance), settings));
+                if let Some(model) = model {
+                    model.line(&turtle.pen, start, turtle.location, origin);
+                }
+                turtle.bend(settings.tropism);
+            },
+            TurtleCommand::RandomMove(distance, spread) => {
+                result.push(turtle.go_to(turtle.ahead(vary(*distance, *spread, random)), settings));
+                if let Some(model) = model {
+                    model.line(&turtle.pen, start, turtle.location, origin);
+                }
+                turtle.bend(settings.tropism);
+            },
+            TurtleCommand::Turn(angle) | TurtleCommand::Yaw(angle) => {
+                turtle.turn(vary(*angle, settings.angle_spread(*angle), random));
+            },
+            TurtleCommand::RandomTurn(angle, spread) => {
+                turtle.turn(vary(*angle, *spread, random));
+            },
+            TurtleCommand::Pitch(angle) => {
+                turtle.pitch(vary(*angle, settings.angle_spread(*angle), random));
+            },
+            TurtleCommand::Roll(angle) => {
+                turtle.roll(vary(*angle, settings.angle_spread(*angle), random));
+            },
+            TurtleCommand::TurnAround => {
+                turtle.turn(180.0);
+            },
+            TurtleCommand::Color(r, g, b) => {
+                result.append(&mut turtle.set_color((*r, *g, *b), settings));
+            },
+            TurtleCommand::ColorIndex(i) => {
+                let palette = settings.palette.len();
+                match settings.palette.get(*i as usize) {
+                    Some(color) => result.append(&mut turtle.set_color(*color, settings)),
+                    None => return Err(TurtleError::PaletteIndex { command: index, index: *i, palette }),
+                }
+            },
+            TurtleCommand::Width(width) => {
+                result.append(&mut turtle.set_width(*width, settings));
+            },
+            TurtleCommand::ScaleWidth(factor) => {
+                result.append(&mut turtle.set_width(turtle.pen.width * factor, settings));
+            },
+            TurtleCommand::Scale(factor) => {
+                turtle.scale *= factor;
+            },
+            TurtleCommand::PolygonBegin => {
+                self.polygons.push((index, Vec::new()));
+            },
+            TurtleCommand::Vertex => {
+                match self.polygons.last_mut() {
+                    Some((_, polygon)) => polygon.push(turtle.location),
+                    None => return Err(TurtleError::NoOpenPolygon { command: index }),
+                }
+            },
+            TurtleCommand::PolygonEnd => {
+                let points = match self.polygons.pop() {
+                    Some((_, points)) => points,
+                    None => return Err(TurtleError::NoOpenPolygon { command: index }),
+                };
+                if let Some(model) = model {
+                    if points.len() > 2 {
+                        model.polygons.push(Polygon { points: points.clone(), color: turtle.pen.color, depth: origin.1 });
                     }
-                },
-                TurtleCommand::Turn(angle) => {
-                    self.orientation += angle;
-                },
-                TurtleCommand::PenDown => {
-                    self.pen.state = PenState::Down;
-                }
-                TurtleCommand::PenUp => {
-                    self.pen.state = PenState::Up;
-                }
-                TurtleCommand::Repeat(n, cs) => {
-                    for _ in 0..*n {
-                        result.append(&mut self.run(cs, stack));
+                }
+                result.append(&mut turtle.end_polygon(&points, settings));
+            },
+            TurtleCommand::Arc(radius, angle) => {
+                if let Some(model) = model {
+                    model.path(&turtle.pen, &turtle.arc_points(*radius, *angle), origin);
+                }
+                result.append(&mut turtle.arc_commands(*radius, *angle, settings));
+            },
+            TurtleCommand::Circle(radius) => {
+                if let Some(model) = model {
+                    model.path(&turtle.pen, &turtle.circle_points(*radius), origin);
+                }
+                result.append(&mut turtle.circle_commands(*radius, settings));
+            },
+            TurtleCommand::Dot(radius) => {
+                if let Some(model) = model {
+                    let mut points = turtle.circle_points(*radius);
+                    points.pop();
+                    model.polygons.push(Polygon { points, color: turtle.pen.color, depth: origin.1 });
+                }
+                result.append(&mut turtle.dot_commands(*radius, settings));
+            },
+            TurtleCommand::Goto(x, y) => {
+                result.push(turtle.go_to(Vec3::new(*x, *y, 0.0), settings));
+                if let Some(model) = model {
+                    model.line(&turtle.pen, start, turtle.location, origin);
+                }
+            },
+            TurtleCommand::Heading(angle) => {
+                turtle.set_heading(*angle);
+            },
+            TurtleCommand::Home => {
+                result.push(turtle.go_to(Vec3::default(), settings));
+                if let Some(model) = model {
+                    model.line(&turtle.pen, start, turtle.location, origin);
+                }
+                turtle.set_heading(0.0);
+            },
+            TurtleCommand::Face(x, y) => {
+                turtle.face(*x, *y);
+            },
+            TurtleCommand::PenDown => {
+                turtle.pen.state = PenState::Down;
+            }
+            TurtleCommand::PenUp => {
+                turtle.pen.state = PenState::Up;
+            }
+            TurtleCommand::Repeat(n, cs) => {
+                for _ in 0..*n {
+                    for c in cs.iter() {
+                        self.run(c, result)?;
                     }
                 }
-                TurtleCommand::Push => {
-                    stack.push(self.clone());
-                },
-                TurtleCommand::Pop => {
-                    if let Some(t) = stack.pop() {
-                        self.location = t.location;
-                        let (x, y) = self.location;
+            }
+            TurtleCommand::Push => {
+                let limit = settings.limits.stack_depth;
+                if self.stack.len() >= limit {
+                    return Err(TurtleError::StackOverflow { command: index, limit });
+                }
+                self.stack.push(turtle.clone());
+            },
+            TurtleCommand::Pop => {
+                if let Some(t) = self.stack.pop() {
+                    let (x, y) = settings.projection.project(t.location);
+                    if t.pen.same_stroke(&turtle.pen) {
                         result.push(DrawCommand::MoveTo(x, y));
-                        self.orientation = t.orientation;
-                        self.pen = t.pen;
-                        result.append(&mut self.pen.run());
                     } else {
-                        web_sys::console::log_1(&"cannot pop an empty stack".into());
+                        result.append(&mut t.pen.restroke((x, y)));
                     }
-                },
-            }
+                    *turtle = t;
+                } else {
+                    return Err(TurtleError::StackUnderflow { command: index });
+                }
+            },
         }
 
-        result
+        let turtle = &self.turtle;
+        if !(turtle.location.is_finite() && turtle.heading.is_finite() && turtle.scale.is_finite()) {
+            return Err(TurtleError::NonFinite { command: index });
+        }
+        Ok(())
+    }
+
+    // closes the last path, once every polygon has been ended
+    pub fn finish(&self) -> Result<Vec<DrawCommand>, TurtleError> {
+        if let Some((command, _)) = self.polygons.first() {
+            return Err(TurtleError::UnclosedPolygon { command: *command });
+        }
+        Ok(vec![DrawCommand::Stroke])
     }
 }
 
 enum Commands {
     Vec(Vec<TurtleCommand>),
-    Stream(Pin<Box<dyn Stream<Item=TurtleCommand>>>),
+    // each command with the symbol it was compiled from, if known
+    Stream(Pin<Box<dyn Stream<Item=(TurtleCommand, Option<char>)>>>),
 }
 
 impl fmt::Debug for Commands {
@@ -122,122 +802,575 @@ impl fmt::Debug for Commands {
     }
 }
 
+// only programs whose commands are held in memory can be serialized
+#[cfg(feature = "serde")]
+impl serde::Serialize for Commands {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Commands::Vec(v) => v.serialize(serializer),
+            Commands::Stream(_) => Err(serde::ser::Error::custom("cannot serialize a streamed program")),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Commands {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Commands::Vec)
+    }
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TurtleProgram {
     turtle: Turtle,
     commands: Commands,
+    #[cfg_attr(feature = "serde", serde(default))]
+    settings: Settings,
+    // the symbol each command was compiled from, for commands held in
+    // memory. streamed commands carry their own
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    symbols: Option<Vec<char>>,
 }
 
 impl TurtleProgram {
     pub fn new(turtle: Turtle, commands: Vec<TurtleCommand>) -> Self {
-        Self { turtle, commands: Commands::Vec(commands) }
+        Self { turtle, commands: Commands::Vec(commands), settings: Settings::default(), symbols: None }
     }
 
     pub fn new_async(turtle: Turtle, commands: Pin<Box<dyn Stream<Item=TurtleCommand>>>) -> Self {
-        Self { turtle, commands: Commands::Stream(commands) }
+        let commands = commands.map(|command| (command, None)).boxed_local();
+        Self { turtle, commands: Commands::Stream(commands), settings: Settings::default(), symbols: None }
     }
 
-    pub fn execute(mut self,
-                   context: web_sys::CanvasRenderingContext2d,
-                   viewport: Viewport) {
+    // like `new_async`, with the symbol each command was compiled from
+    pub fn new_async_with_symbols(turtle: Turtle, commands: Pin<Box<dyn Stream<Item=(TurtleCommand, char)>>>) -> Self {
+        let commands = commands.map(|(command, symbol)| (command, Some(symbol))).boxed_local();
+        Self { turtle, commands: Commands::Stream(commands), settings: Settings::default(), symbols: None }
+    }
+
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        self.settings.projection = projection;
+        self
+    }
+
+    pub fn with_palette(mut self, palette: Vec<Color>) -> Self {
+        self.settings.palette = palette;
+        self
+    }
+
+    pub fn with_tropism(mut self, tropism: Tropism) -> Self {
+        self.settings.tropism = Some(tropism);
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.settings.jitter = Some(jitter);
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.settings.limits = limits;
+        self
+    }
+
+    // the symbol each command was compiled from, in the order of the
+    // commands
+    pub fn with_symbols(mut self, symbols: Vec<char>) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json(input: &str) -> Result<Self, String> {
+        serde_json::from_str(input).map_err(|err| format!("json error: {}", err))
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|err| format!("json error: {}", err))
+    }
+
+    // everything the program draws, start to finish, or the first error.
+    // a streamed program is run to completion first
+    pub fn draw_commands(self) -> Result<Vec<DrawCommand>, TurtleError> {
         match self.commands {
             Commands::Vec(commands) => {
-                let mut draw_commands = Vec::new();
-                let mut stack = Vec::new();
-                // start stuff
-                draw_commands.push(DrawCommand::BeginPath);
-                draw_commands.append(&mut self.turtle.pen.run());
-                let (x, y) = self.turtle.location;
-                draw_commands.push(DrawCommand::MoveTo(x, y));
-                // middle stuff
-                draw_commands.append(&mut self.turtle.run(&commands, &mut stack));
-                // end stuff
-                draw_commands.push(DrawCommand::Stroke);
-                // do it!
-                DrawCommand::exec_all(&draw_commands, &context, viewport);
+                let mut executor = Executor::new(self.turtle, self.settings);
+                let mut result = executor.start();
+                for command in commands.iter() {
+                    result.append(&mut executor.step(command)?);
+                }
+                result.append(&mut executor.finish()?);
+                Ok(result)
             }
-            Commands::Stream(commands) => {
-                wasm_bindgen_futures::spawn_local(async move {
-                    let turtle = Rc::new(RefCell::new(self.turtle.clone()));
-                    let stack = Rc::new(RefCell::new(Vec::<Turtle>::new()));
-                    let mut draw_commands: Pin<Box<dyn Stream<Item=DrawCommand>>> =
-                        stream::iter(vec![
-                            //start stuff
-                            stream::once(future::ready(DrawCommand::BeginPath)).boxed_local(),
-                            turtle.clone().borrow().pen.run_as_stream(),
-                            stream::once(future::ready(DrawCommand::BeginPath)).boxed_local(),
-                            // middle stuff
-                            commands.flat_map(move |command|
-                                run_command(turtle.clone(), &command, stack.clone())
-                            ).boxed_local(),
-                            // end stuff
-                            stream::once(future::ready(DrawCommand::Stroke)).boxed_local(),
-                        ]).flatten().boxed_local();
-                    // do it!
-                    DrawCommand::exec_stream(&mut draw_commands, &context, viewport).await;
-
-                });
+            Commands::Stream(_) => futures::executor::block_on(self.draw_stream().try_collect()),
+        }
+    }
+
+    // what the program draws in turtle space, before projection, or the
+    // first error. a streamed program is run to completion first
+    pub fn model(self) -> Result<Model, TurtleError> {
+        let (commands, symbols): (Vec<TurtleCommand>, Vec<Option<char>>) = match self.commands {
+            Commands::Vec(commands) => {
+                let symbols = self.symbols.unwrap_or_default().into_iter().map(Some).collect();
+                (commands, symbols)
             }
+            Commands::Stream(commands) => futures::executor::block_on(commands.collect::<Vec<_>>()).into_iter().unzip(),
+        };
+        let mut executor = Executor::new(self.turtle, self.settings).recording();
+        for command in commands.iter() {
+            executor.step(command)?;
+        }
+        executor.finish()?;
+        let mut model = executor.model.unwrap_or_default();
+        for segment in model.segments.iter_mut() {
+            segment.symbol = symbols.get(segment.command).copied().flatten();
+        }
+        Ok(model)
+    }
+
+    // every straight stretch the program draws, in the order it draws them
+    pub fn segments(self) -> Result<Vec<Segment>, TurtleError> {
+        self.model().map(|model| model.segments)
+    }
+
+    // the same commands as `draw_commands`, produced as the program's
+    // commands arrive. the stream ends after the first error
+    pub fn draw_stream(self) -> Pin<Box<dyn Stream<Item=Result<DrawCommand, TurtleError>>>> {
+        let commands = match self.commands {
+            Commands::Vec(commands) => stream::iter(commands).boxed_local(),
+            Commands::Stream(commands) => commands.map(|(command, _)| command).boxed_local(),
+        };
+        let executor = Rc::new(RefCell::new(Executor::new(self.turtle, self.settings)));
+        let start = executor.borrow().start();
+        let finish = executor.clone();
+        stream::iter(start).map(Ok)
+            .chain(commands.flat_map(move |command| {
+                match executor.borrow_mut().step(&command) {
+                    Ok(v) => stream::iter(v).map(Ok).boxed_local(),
+                    Err(err) => stream::once(future::ready(Err(err))).boxed_local(),
+                }
+            }))
+            .chain(stream::once(future::lazy(move |_| match finish.borrow().finish() {
+                Ok(v) => stream::iter(v).map(Ok).boxed_local(),
+                Err(err) => stream::once(future::ready(Err(err))).boxed_local(),
+            })).flatten())
+            .scan(false, |failed, item| {
+                if *failed {
+                    return future::ready(None);
+                }
+                *failed = item.is_err();
+                future::ready(Some(item))
+            })
+            .boxed_local()
+    }
+
+    // nothing is drawn when the program fails
+    pub fn render(self, target: &mut impl RenderTarget) -> Result<(), TurtleError> {
+        target.render_all(&self.draw_commands()?);
+        Ok(())
+    }
+
+    pub fn execute(self,
+                   context: web_sys::CanvasRenderingContext2d,
+                   viewport: Viewport) -> Result<(), TurtleError> {
+        self.render(&mut Canvas::new(context, viewport))
+    }
+
+    // draws the commands as they are produced, up to the first error
+    pub async fn execute_async(self,
+                               context: web_sys::CanvasRenderingContext2d,
+                               viewport: Viewport) -> Result<(), TurtleError> {
+        let error = Rc::new(RefCell::new(None));
+        let failure = error.clone();
+        let mut draw_commands = self.draw_stream()
+            .filter_map(move |item| future::ready(match item {
+                Ok(command) => Some(command),
+                Err(err) => {
+                    *failure.borrow_mut() = Some(err);
+                    None
+                }
+            }))
+            .boxed_local();
+        render_stream(&mut Canvas::new(context, viewport), &mut draw_commands).await;
+        let result = error.borrow_mut().take();
+        match result {
+            Some(err) => Err(err),
+            None => Ok(()),
         }
     }
 }
 
-pub fn run_command(turtle: Rc<RefCell<Turtle>>,
-                   command: &TurtleCommand,
-                   stack: Rc<RefCell<Vec<Turtle>>>) -> Pin<Box<dyn Stream<Item=DrawCommand>>> {
-    use TurtleCommand::*;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::{FRAC_PI_2, TAU};
+
+    fn turtle() -> Turtle {
+        Turtle::new(Pen { color: (1.0, 1.0, 1.0), width: 1.0, state: PenState::Down })
+    }
+
+    // runs `commands` without the commands that open and close the drawing
+    fn run(commands: &[TurtleCommand], settings: &Settings) -> (Executor, Vec<DrawCommand>) {
+        let mut executor = Executor::new(turtle(), settings.clone());
+        let mut result = Vec::new();
+        for command in commands.iter() {
+            result.append(&mut executor.step(command).unwrap());
+        }
+        (executor, result)
+    }
+
+    fn assert_close(actual: Vec3, expected: Vec3) {
+        assert!((actual - expected).length() < 1e-9, "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn turn_is_counter_clockwise() {
+        let commands = vec![TurtleCommand::Turn(90.0), TurtleCommand::Move(10.0)];
+        let (executor, _) = run(&commands, &Settings::default());
+        let turtle = &executor.turtle;
+        assert_close(turtle.location, Vec3::new(0.0, 10.0, 0.0));
+    }
+
+    #[test]
+    fn pitch_and_roll() {
+        let (executor, _) = run(&[TurtleCommand::Pitch(90.0)], &Settings::default());
+        let turtle = &executor.turtle;
+        assert_close(turtle.heading, -Vec3::Z);
+        assert_close(turtle.up, Vec3::X);
+
+        let (executor, _) = run(&[TurtleCommand::Roll(90.0), TurtleCommand::Turn(90.0)], &Settings::default());
+        let turtle = &executor.turtle;
+        assert_close(turtle.heading, Vec3::Z);
+        assert_close(turtle.left, -Vec3::X);
+        assert_close(turtle.up, -Vec3::Y);
+
+        let (executor, _) = run(&[TurtleCommand::Roll(90.0), TurtleCommand::Yaw(90.0)], &Settings::default());
+        let turtle = &executor.turtle;
+        assert_close(turtle.heading, Vec3::Z);
+        assert_close(turtle.up, -Vec3::Y);
+
+        let (executor, _) = run(&[TurtleCommand::TurnAround], &Settings::default());
+        let turtle = &executor.turtle;
+        assert_close(turtle.heading, -Vec3::X);
+        assert_close(turtle.up, Vec3::Z);
+    }
+
+    #[test]
+    fn projection() {
+        let p = Vec3::new(1.0, 2.0, 3.0);
+        assert_eq!(Projection::default().project(p), (1.0, 2.0));
+        let (x, y) = Projection { azimuth: 0.0, elevation: 90.0 }.project(p);
+        assert!((x - 1.0).abs() < 1e-9 && (y - 3.0).abs() < 1e-9);
+        let (x, y) = Projection { azimuth: 90.0, elevation: 0.0 }.project(p);
+        assert!((x + 2.0).abs() < 1e-9 && (y - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn colors() {
+        let settings = Settings { palette: vec![(0.0, 0.0, 1.0)], ..Settings::default() };
+        let commands = vec![
+            TurtleCommand::Push,
+            TurtleCommand::Color(1.0, 0.0, 0.0),
+            TurtleCommand::Move(10.0),
+            TurtleCommand::Pop,
+            TurtleCommand::ColorIndex(0),
+            TurtleCommand::ColorIndex(0),
+        ];
+        let (_, actual) = run(&commands, &settings);
+        let restroke = |style: &str| vec![
+            DrawCommand::Stroke,
+            DrawCommand::BeginPath,
+            DrawCommand::SetLineWidth(1.0),
+            DrawCommand::SetStrokeStyle(style.to_string()),
+            DrawCommand::MoveTo(0.0, 0.0),
+        ];
+        let mut expected = restroke("#ff0000");
+        expected.push(DrawCommand::LineTo(10.0, 0.0));
+        expected.append(&mut restroke("#ffffff"));
+        expected.append(&mut restroke("#0000ff"));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn width_and_scale() {
+        let commands = vec![
+            TurtleCommand::Push,
+            TurtleCommand::Scale(0.5),
+            TurtleCommand::Width(4.0),
+            TurtleCommand::ScaleWidth(0.5),
+            TurtleCommand::Move(10.0),
+            TurtleCommand::Pop,
+            TurtleCommand::Move(10.0),
+        ];
+        let (_, actual) = run(&commands, &Settings::default());
+        let restroke = |width: f64, x: f64| vec![
+            DrawCommand::Stroke,
+            DrawCommand::BeginPath,
+            DrawCommand::SetLineWidth(width),
+            DrawCommand::SetStrokeStyle("#ffffff".to_string()),
+            DrawCommand::MoveTo(x, 0.0),
+        ];
+        let mut expected = restroke(4.0, 0.0);
+        expected.append(&mut restroke(2.0, 0.0));
+        expected.push(DrawCommand::LineTo(5.0, 0.0));
+        expected.append(&mut restroke(1.0, 0.0));
+        expected.push(DrawCommand::LineTo(10.0, 0.0));
+        assert_eq!(actual, expected);
+    }
 
-    match command {
-        Move(distance) => {
-            let (angle, (x, y)) = (turtle.borrow().orientation, turtle.borrow().location);
-            let x = x + distance * angle.to_radians().cos();
-            let y = y + distance * angle.to_radians().sin();
-            turtle.borrow_mut().location = (x, y);
-            if turtle.borrow().pen.state == PenState::Down {
-                return stream::once(future::ready(DrawCommand::LineTo(x,y))).boxed_local();
-            } else {
-                return stream::once(future::ready(DrawCommand::MoveTo(x,y))).boxed_local();
+    #[test]
+    fn polygons() {
+        let commands = vec![
+            TurtleCommand::PolygonBegin,
+            TurtleCommand::Vertex,
+            TurtleCommand::PenUp,
+            TurtleCommand::Push,
+            TurtleCommand::Move(10.0),
+            TurtleCommand::Vertex,
+            TurtleCommand::Pop,
+            TurtleCommand::Turn(90.0),
+            TurtleCommand::Move(10.0),
+            TurtleCommand::Vertex,
+            TurtleCommand::PolygonEnd,
+        ];
+        let (executor, actual) = run(&commands, &Settings::default());
+        let fill = actual.iter().position(|c| *c == DrawCommand::SetFillStyle("#ffffff".to_string())).unwrap();
+        assert_eq!(actual[fill + 1], DrawCommand::MoveTo(0.0, 0.0));
+        assert_eq!(actual[fill + 2], DrawCommand::LineTo(10.0, 0.0));
+        assert!(matches!(actual[fill + 3], DrawCommand::LineTo(x, y) if x.abs() < 1e-9 && y == 10.0));
+        assert_eq!(actual[fill + 4..fill + 6], [DrawCommand::ClosePath, DrawCommand::Fill]);
+        assert!(executor.polygons.is_empty());
+    }
+
+    #[test]
+    fn arcs() {
+        let (executor, actual) = run(&[TurtleCommand::Arc(10.0, 90.0)], &Settings::default());
+        let turtle = &executor.turtle;
+        assert_close(turtle.location, Vec3::new(10.0, 10.0, 0.0));
+        assert_close(turtle.heading, Vec3::Y);
+        match actual[..] {
+            [DrawCommand::Arc(cx, cy, r, start, end)] => {
+                assert!(cx.abs() < 1e-9 && (cy - 10.0).abs() < 1e-9 && r == 10.0);
+                assert!((start + FRAC_PI_2).abs() < 1e-9 && end.abs() < 1e-9);
             }
+            _ => panic!("expected an arc, got {:?}", actual),
         }
-        Turn(angle) => {
-            turtle.borrow_mut().orientation += angle;
-            return stream::empty().boxed_local();
-        }
-        PenDown => {
-            turtle.borrow_mut().pen.state = PenState::Down;
-            return stream::empty().boxed_local();
-        }
-        PenUp => {
-            turtle.borrow_mut().pen.state = PenState::Up;
-            return stream::empty().boxed_local();
-        }
-        Repeat(n, cs) => {
-            let n = *n;
-            let cs = cs.clone();
-            return stream::iter(0..n).flat_map(move |_| {
-                let turtle = turtle.clone();
-                let stack = stack.clone();
-                stream::iter(cs.clone()).flat_map(move |c| {
-                    run_command(turtle.clone(), &c, stack.clone())
-                }).boxed_local()
-            }).boxed_local();
-        }
-        Push => {
-            stack.borrow_mut().push(turtle.borrow().clone());
-            return stream::empty().boxed_local();
-        },
-        Pop => {
-            if let Some(t) = stack.borrow_mut().pop() {
-                let (x, y) = t.location;
-                *turtle.borrow_mut() = t;
-                let mut v = turtle.borrow().pen.run();
-                v.push(DrawCommand::MoveTo(x, y));
-                return stream::iter(v).boxed_local();
-            } else {
-                web_sys::console::log_1(&"cannot pop an empty stack".into());
-                return stream::empty().boxed_local();
+
+        let (executor, _) = run(&[TurtleCommand::Arc(10.0, -180.0)], &Settings::default());
+        let turtle = &executor.turtle;
+        assert_close(turtle.location, Vec3::new(0.0, -20.0, 0.0));
+        assert_close(turtle.heading, -Vec3::X);
+
+        // a negative radius draws the same arc, forwards
+        let (executor, negative) = run(&[TurtleCommand::Arc(-10.0, 90.0)], &Settings::default());
+        let turtle = &executor.turtle;
+        assert_close(turtle.location, Vec3::new(10.0, 10.0, 0.0));
+        assert_close(turtle.heading, Vec3::Y);
+        assert_eq!(negative, actual);
+
+        let (_, actual) = run(&[TurtleCommand::Pitch(45.0), TurtleCommand::Arc(10.0, 90.0)], &Settings::default());
+        assert_eq!(actual.len(), 9);
+        assert!(actual.iter().all(|c| matches!(c, DrawCommand::LineTo(_, _))));
+    }
+
+    #[test]
+    fn circles_and_dots() {
+        let (executor, actual) = run(&[TurtleCommand::Circle(5.0), TurtleCommand::Dot(2.0)], &Settings::default());
+        let turtle = &executor.turtle;
+        assert_close(turtle.location, Vec3::default());
+        assert_eq!(actual[..3], [
+            DrawCommand::MoveTo(5.0, 0.0),
+            DrawCommand::Arc(0.0, 0.0, 5.0, 0.0, TAU),
+            DrawCommand::MoveTo(0.0, 0.0),
+        ]);
+        assert_eq!(actual[5..8], [
+            DrawCommand::SetFillStyle("#ffffff".to_string()),
+            DrawCommand::MoveTo(2.0, 0.0),
+            DrawCommand::Arc(0.0, 0.0, 2.0, 0.0, TAU),
+        ]);
+    }
+
+    #[test]
+    fn absolute_positioning() {
+        let commands = vec![
+            TurtleCommand::Scale(2.0),
+            TurtleCommand::Pitch(30.0),
+            TurtleCommand::Push,
+            TurtleCommand::PenUp,
+            TurtleCommand::Goto(10.0, 10.0),
+            TurtleCommand::Face(10.0, 0.0),
+            TurtleCommand::Move(5.0),
+            TurtleCommand::PenDown,
+            TurtleCommand::Home,
+            TurtleCommand::Pop,
+            TurtleCommand::Heading(180.0),
+            TurtleCommand::Move(1.0),
+        ];
+        let (executor, actual) = run(&commands, &Settings::default());
+        let turtle = &executor.turtle;
+        assert_eq!(actual[0], DrawCommand::MoveTo(10.0, 10.0));
+        assert!(matches!(actual[1], DrawCommand::MoveTo(x, y) if (x - 10.0).abs() < 1e-9 && y.abs() < 1e-9));
+        assert_eq!(actual[2], DrawCommand::LineTo(0.0, 0.0));
+        assert_close(turtle.location, Vec3::new(-2.0, 0.0, 0.0));
+        assert_close(turtle.heading, -Vec3::X);
+        assert_close(turtle.up, Vec3::Z);
+    }
+
+    #[test]
+    fn tropism() {
+        let settings = Settings {
+            tropism: Some(Tropism { direction: -Vec3::Y, susceptibility: 0.5 }),
+            ..Settings::default()
+        };
+        let (executor, _) = run(&[TurtleCommand::Move(10.0)], &settings);
+        let turtle = &executor.turtle;
+        assert_close(turtle.location, Vec3::new(10.0, 0.0, 0.0));
+        let (sin, cos) = 0.5f64.sin_cos();
+        assert_close(turtle.heading, Vec3::new(cos, -sin, 0.0));
+        assert_close(turtle.left, Vec3::new(sin, cos, 0.0));
+        assert_close(turtle.up, Vec3::Z);
+
+        // a heading along the tropism vector is left alone
+        let commands = vec![TurtleCommand::Push, TurtleCommand::Turn(-90.0), TurtleCommand::Move(10.0)];
+        let (executor, _) = run(&commands, &settings);
+        assert_close(executor.turtle.heading, -Vec3::Y);
+        assert_close(executor.stack[0].heading, Vec3::X);
+    }
+
+    #[test]
+    fn jitter() {
+        let commands = vec![TurtleCommand::Repeat(20, vec![TurtleCommand::Move(10.0), TurtleCommand::Turn(30.0)])];
+        let settings = |seed| Settings {
+            jitter: Some(Jitter { angle: 0.1, length: 0.2, seed }),
+            ..Settings::default()
+        };
+        let draw = |settings: &Settings| run(&commands, settings).1;
+        assert_eq!(draw(&settings(1)), draw(&settings(1)));
+        assert_ne!(draw(&settings(1)), draw(&settings(2)));
+        assert_ne!(draw(&settings(1)), draw(&Settings::default()));
+
+        let mut executor = Executor::new(turtle(), settings(0));
+        for _ in 0..100 {
+            let before = executor.turtle.location;
+            executor.step(&TurtleCommand::RandomMove(10.0, 2.0)).unwrap();
+            let distance = (executor.turtle.location - before).length();
+            assert!((8.0..=12.0).contains(&distance), "{}", distance);
+        }
+    }
+
+    #[test]
+    fn executors_agree() {
+        for (name, _, lsystem) in crate::examples::all_examples() {
+            for iterations in 0..4 {
+                let expected = lsystem().compile(iterations).draw_commands().unwrap();
+                let actual = lsystem().compile_stream(iterations).draw_commands().unwrap();
+                assert_eq!(actual, expected, "{} after {} iterations", name, iterations);
             }
-        },
+        }
+
+        let jittered = crate::parser::parse(
+            "LSYSTEM (F, (F -> F[+F]F), (F -> (MOVE 10 ~2), + -> (TURN 25), [ -> (PUSH), ] -> (POP)), JITTER angle=10% seed=3)"
+        ).unwrap();
+        assert_eq!(jittered.compile_stream(3).draw_commands(), jittered.compile(3).draw_commands());
+
+        let expected = crate::examples::turtle_example().draw_commands().unwrap();
+        let actual: Vec<DrawCommand> = futures::executor::block_on(crate::examples::turtle_example().draw_stream().try_collect()).unwrap();
+        assert_eq!(actual, expected);
+        assert_eq!(expected[..4], [
+            DrawCommand::BeginPath,
+            DrawCommand::SetLineWidth(3.0),
+            DrawCommand::SetStrokeStyle("#ffffff".to_string()),
+            DrawCommand::MoveTo(0.0, 0.0),
+        ]);
+        assert_eq!(expected.last(), Some(&DrawCommand::Stroke));
     }
-}
 
+    #[test]
+    fn model() {
+        let commands = vec![
+            TurtleCommand::Pitch(-90.0),
+            TurtleCommand::Move(10.0),
+            TurtleCommand::PenUp,
+            TurtleCommand::Move(1.0),
+            TurtleCommand::PenDown,
+            TurtleCommand::Width(2.0),
+            TurtleCommand::Color(1.0, 0.0, 0.0),
+            TurtleCommand::PolygonBegin,
+            TurtleCommand::Vertex,
+            TurtleCommand::Goto(0.0, 0.0),
+            TurtleCommand::Vertex,
+            TurtleCommand::Turn(90.0),
+            TurtleCommand::Move(5.0),
+            TurtleCommand::Vertex,
+            TurtleCommand::PolygonEnd,
+            TurtleCommand::Circle(1.0),
+        ];
+        let model = TurtleProgram::new(turtle(), commands).model().unwrap();
+        assert_close(model.segments[0].start, Vec3::default());
+        assert_close(model.segments[0].end, Vec3::new(0.0, 0.0, 10.0));
+        assert_eq!((model.segments[0].width, model.segments[0].color), (1.0, (1.0, 1.0, 1.0)));
+        assert_close(model.segments[1].start, Vec3::new(0.0, 0.0, 11.0));
+        assert_eq!((model.segments[1].width, model.segments[1].color), (2.0, (1.0, 0.0, 0.0)));
+        assert_eq!(model.segments.len(), 3 + 36);
+        assert_eq!(model.polygons.len(), 1);
+        assert_close(model.polygons[0].points[2], Vec3::new(0.0, 5.0, 0.0));
+        assert_eq!(model.polygons[0].color, (1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn errors() {
+        let program = |commands: Vec<TurtleCommand>| TurtleProgram::new(turtle(), commands);
+        let stream = |commands: Vec<TurtleCommand>| TurtleProgram::new_async(turtle(), stream::iter(commands).boxed_local());
+
+        let commands = vec![TurtleCommand::Push, TurtleCommand::Pop, TurtleCommand::Move(1.0), TurtleCommand::Pop];
+        assert_eq!(program(commands.clone()).draw_commands(), Err(TurtleError::StackUnderflow { command: 3 }));
+        let streamed: Vec<_> = futures::executor::block_on(stream(commands).draw_stream().collect());
+        assert_eq!(streamed.last(), Some(&Err(TurtleError::StackUnderflow { command: 3 })));
+        assert_eq!(streamed.iter().filter(|item| item.is_err()).count(), 1);
+
+        let commands = vec![TurtleCommand::Move(1.0), TurtleCommand::Repeat(1000, vec![TurtleCommand::Push])];
+        let limits = Limits { stack_depth: 100, ..Limits::default() };
+        assert_eq!(
+            program(commands.clone()).with_limits(limits).draw_commands(),
+            Err(TurtleError::StackOverflow { command: 1, limit: 100 }),
+        );
+        let limits = Limits { commands: 50, ..Limits::default() };
+        assert_eq!(
+            stream(commands).with_limits(limits).draw_commands(),
+            Err(TurtleError::CommandBudgetExceeded { command: 1, budget: 50 }),
+        );
+
+        let commands = vec![TurtleCommand::Scale(1e300), TurtleCommand::Scale(1e300), TurtleCommand::Move(1.0)];
+        assert_eq!(program(commands).draw_commands(), Err(TurtleError::NonFinite { command: 1 }));
+        assert_eq!(program(vec![TurtleCommand::Move(f64::NAN)]).draw_commands(), Err(TurtleError::NonFinite { command: 0 }));
+        assert_eq!(TurtleError::NonFinite { command: 4 }.to_string(), "command 4: the turtle left finite space");
+
+        let commands = vec![TurtleCommand::ColorIndex(0), TurtleCommand::ColorIndex(1)];
+        let error = TurtleError::PaletteIndex { command: 1, index: 1, palette: 1 };
+        assert_eq!(program(commands).with_palette(vec![(1.0, 0.0, 0.0)]).draw_commands(), Err(error.clone()));
+        assert_eq!(error.to_string(), "command 1: no color 1 in a palette of 1");
+        assert_eq!(
+            program(vec![TurtleCommand::ColorIndex(0)]).draw_commands(),
+            Err(TurtleError::PaletteIndex { command: 0, index: 0, palette: 0 }),
+        );
+    }
+
+    #[test]
+    fn unbalanced_polygons() {
+        let program = |commands: Vec<TurtleCommand>| TurtleProgram::new(turtle(), commands);
+        let stream = |commands: Vec<TurtleCommand>| TurtleProgram::new_async(turtle(), stream::iter(commands).boxed_local());
+
+        let commands = vec![TurtleCommand::PolygonBegin, TurtleCommand::PolygonEnd, TurtleCommand::Vertex];
+        assert_eq!(program(commands).draw_commands(), Err(TurtleError::NoOpenPolygon { command: 2 }));
+        let commands = vec![TurtleCommand::PolygonBegin, TurtleCommand::PolygonEnd, TurtleCommand::PolygonEnd];
+        assert_eq!(program(commands).model().map(|_| ()), Err(TurtleError::NoOpenPolygon { command: 2 }));
+        let commands = vec![TurtleCommand::Move(1.0), TurtleCommand::PolygonBegin, TurtleCommand::PolygonBegin, TurtleCommand::PolygonEnd];
+        assert_eq!(program(commands.clone()).draw_commands(), Err(TurtleError::UnclosedPolygon { command: 1 }));
+        assert_eq!(program(commands.clone()).model().map(|_| ()), Err(TurtleError::UnclosedPolygon { command: 1 }));
+        let streamed: Vec<_> = futures::executor::block_on(stream(commands).draw_stream().collect());
+        assert_eq!(streamed.last(), Some(&Err(TurtleError::UnclosedPolygon { command: 1 })));
+        assert_eq!(TurtleError::UnclosedPolygon { command: 1 }.to_string(), "command 1: the polygon is never ended");
+    }
+}
@@ -1,15 +1,15 @@
-use web_sys;
 use wasm_bindgen::prelude::*;
 
+use crate::draw::BACKGROUND;
+
 pub fn get_canvas() -> web_sys::HtmlCanvasElement {
     let window = web_sys::window().unwrap();
     let document = window.document().unwrap();
-    let canvas = document
+    document
         .get_element_by_id("canvas")
         .unwrap()
         .dyn_into::<web_sys::HtmlCanvasElement>()
-        .unwrap();
-    canvas
+        .unwrap()
 }
 
 pub fn get_context2d() -> web_sys::CanvasRenderingContext2d {
@@ -22,7 +22,7 @@ pub fn clear_canvas(context: &web_sys::CanvasRenderingContext2d) {
     let canvas = context.canvas().expect("canvas is missing");
     let (width, height) = (canvas.width() as f64, canvas.height() as f64);
     context.begin_path();
-    context.set_fill_style(&JsValue::from_str("#111111"));
+    context.set_fill_style(&JsValue::from_str(BACKGROUND));
     context.rect(0.0, 0.0, width, height);
     context.fill();
 }